@@ -1,6 +1,18 @@
+use crate::{
+    config_options,
+    export::Lang,
+    filter::{parse_filter, Filter},
+    profile::read_profile,
+    MAX_PCAP_BUFFER,
+};
 use clap::{
-    crate_authors, crate_description, crate_name, crate_version, Arg, ArgAction,
-    Command,
+    crate_authors, crate_description, crate_name, crate_version, error::ErrorKind,
+    Arg, ArgAction, ArgGroup, Command,
+};
+use std::{
+    ffi::OsString,
+    net::{IpAddr, SocketAddr},
+    path::Path,
 };
 
 pub fn get_styles() -> clap::builder::Styles {
@@ -21,10 +33,19 @@ pub fn get_styles() -> clap::builder::Styles {
         )
 }
 
+// tools that work on configs instead of viewing data
+pub enum Subcommand {
+    Convert { infile: String, outfile: String },
+    ImportC { header: String, outfile: String },
+    Export { lang: Lang, config: String, outfile: String, udp_port: Option<u16> },
+    Layout { config: String, diagram: bool, svg: String },
+}
+
 pub struct Args {
     pub infile: String,
     pub outfile: String,
     pub config: String,
+    pub mask_lines: Vec<String>,
     pub pcap: bool,
     pub chunksize: usize,
     pub offset: usize,
@@ -36,16 +57,41 @@ pub struct Args {
     pub little_endian: bool,
     pub timestamp: bool,
     pub read_head: usize,
+    pub stream: bool,
+    pub follow: bool,
+    pub udp: Option<SocketAddr>,
+    pub join: Vec<IpAddr>,
     pub print_statistics: bool,
     pub print_bitpos: bool,
+    pub layout: bool,
+    pub strip_headers: bool,
+    pub print_addresses: bool,
+    pub filter: Option<Filter>,
+    pub pcap_buffer: usize,
     pub cursor_jump: bool,
     pub clear: bool,
     pub filter_newlines: bool,
+    pub select: String,
+    pub subcommand: Option<Subcommand>,
 }
 
+// flags that a profile or the config can switch on, and the flags that switch
+// them off again, whichever comes last wins
+const NEGATED_FLAGS: [(&str, &str, &str); 9] = [
+    ("pcap", "pcap", "no-pcap"),
+    ("little endian", "little-endian", "no-little-endian"),
+    ("rawhex", "rawhex", "no-rawhex"),
+    ("rawbin", "rawbin", "no-rawbin"),
+    ("rawascii", "rawascii", "no-rawascii"),
+    ("timestamp", "timestamp", "no-timestamp"),
+    ("print statistics", "stats", "no-stats"),
+    ("no cursor jumping", "nojump", "jump"),
+    ("clear", "clear", "no-clear"),
+];
+
 impl Args {
     pub fn command() -> Command {
-        Command::new(crate_name!())
+        let command = Command::new(crate_name!())
             .styles(get_styles())
             .version(crate_version!())
             .author(crate_authors!("\n"))
@@ -59,6 +105,80 @@ impl Args {
 {all-args}{after-help}
             ",
             )
+            .subcommand_negates_reqs(true)
+            // options given twice, like in the config and on the command line, take the last one
+            .args_override_self(true)
+            .subcommand(
+                Command::new("convert")
+                    .about("Convert a config between the line format and TOML, YAML or JSON")
+                    .long_about("Convert a config between the line format and TOML, \
+                                 YAML or JSON. The format of each file is told by \
+                                 its extension (.toml, .yaml, .yml, .json), any \
+                                 other file is a config in the line format.")
+                    .arg(Arg::new("input").required(true).help("Config to convert"))
+                    .arg(Arg::new("output").required(true).help("File to write the converted config to")),
+            )
+            .subcommand(
+                Command::new("import-c")
+                    .about("Generate a config from a struct of a C header")
+                    .long_about("Generate a config from a struct of a C header. \
+                                 The struct is laid out like GCC does it, with \
+                                 padding, packing and bitfields, for the byte \
+                                 order given with --le (big endian otherwise). \
+                                 Without output the config is printed.")
+                    .arg(Arg::new("header").required(true).help("C header with the struct"))
+                    .arg(Arg::new("output").help("File to write the config to, .toml, .yaml or .json for a structured config")),
+            )
+            .subcommand(
+                Command::new("export")
+                    .about("Generate a C struct, Rust decode code or a Wireshark dissector from a config")
+                    .long_about("Generate a packed C struct, a Rust struct with \
+                                 a decode function or a Wireshark dissector in \
+                                 Lua from a config. Fields keep their position, \
+                                 bit order and byte order, multi-byte fields \
+                                 without le or be use the byte order given with \
+                                 --le. Without output the code is printed.")
+                    .arg(Arg::new("lang")
+                            .long("lang")
+                            .required_unless_present("wireshark-lua")
+                            .value_parser(["c", "rust", "lua"])
+                            .help("Language of the generated code"))
+                    .arg(Arg::new("wireshark-lua")
+                            .long("wireshark-lua")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("lang")
+                            .help("Generate a Wireshark dissector, the same as --lang lua"))
+                    .arg(Arg::new("udp-port")
+                            .long("udp-port")
+                            .value_parser(clap::value_parser!(u16))
+                            .help("UDP port the dissector is registered for, only with --lang lua")
+                            .long_help("UDP port the dissector is registered for, \
+                                        only with --lang lua or --wireshark-lua. \
+                                        Without it the dissector can be chosen \
+                                        with Decode As in Wireshark."))
+                    .arg(Arg::new("config").required(true).help("Config to export"))
+                    .arg(Arg::new("output").help("File to write the code to")),
+            )
+            .subcommand(
+                Command::new("layout")
+                    .about("Show where the fields of a config are in the chunk")
+                    .long_about("Show where the fields of a config are in the \
+                                 chunk. Without options a table of the offset \
+                                 and size of each field is printed, like with \
+                                 --layout. With --diagram the fields are drawn \
+                                 as a bit diagram with 32 bits in a row, like \
+                                 in the RFCs. With --svg the diagram is also \
+                                 written as an SVG image.")
+                    .arg(Arg::new("diagram")
+                            .long("diagram")
+                            .action(ArgAction::SetTrue)
+                            .help("Print an ASCII bit diagram of the fields"))
+                    .arg(Arg::new("svg")
+                            .long("svg")
+                            .value_name("FILE")
+                            .help("Write the bit diagram as SVG to FILE"))
+                    .arg(Arg::new("config").required(true).help("Config to show")),
+            )
             .arg(Arg::new("infile")
                     .short('i')
                     .long("infile")
@@ -76,15 +196,79 @@ impl Args {
                     .short('c')
                     .long("config")
 
-					.required(true)
+					.required_unless_present_any(["field", "mask", "profile"])
                     .help("Definition of the datafields of a chunk"),
             )
+            .arg(
+                Arg::new("profile")
+                    .long("profile")
+                    .help("Use the options of a profile of ~/.config/mview/config.toml")
+                    .long_help("Use the options of a profile of \
+                                ~/.config/mview/config.toml (or of \
+                                $XDG_CONFIG_HOME/mview/config.toml). The \
+                                options of the profile override the ones in \
+                                the config, the ones on the command line \
+                                override the profile."),
+            )
+            .arg(
+                Arg::new("field")
+                    .short('f')
+                    .long("field")
+                    .action(ArgAction::Append)
+                    .help("A line of the config given on the command line, can be repeated")
+                    .long_help("A line of the config given on the command \
+                                line, like -f 'Len:u16' -f 'Name:string:8'. \
+                                Can be repeated and combined with --mask and \
+                                --config, the lines are added behind the \
+                                config file in the order they are given."),
+            )
+            .arg(
+                Arg::new("mask")
+                    .long("mask")
+                    .action(ArgAction::Append)
+                    .help("Lines of the config given on the command line, separated by commas")
+                    .long_help("Lines of the config given on the command line, \
+                                separated by commas, like --mask \
+                                'Len:u16,Name:string:8'. Commas inside of \
+                                enum(...) do not separate lines."),
+            )
+            .arg(
+                Arg::new("struct")
+                    .long("struct")
+                    .visible_alias("message")
+                    .global(true)
+                    .help("Struct of a C header or message of a DBC file to use as mask")
+                    .long_help("Struct of a C header or message of a DBC file \
+                                to use as mask. A config file ending in .h is \
+                                read as a C header, one ending in .dbc as a \
+                                DBC file. If it defines more than one struct or \
+                                message the one to use is chosen with this \
+                                argument, messages by name or CAN id."),
+            )
             .arg(
                 Arg::new("pcap")
                     .long("pcap")
                     .action(ArgAction::SetTrue)
                     .help("Read from a PCAP formatted file or data stream"),
             )
+            .arg(
+                Arg::new("udp")
+                    .long("udp")
+                    .value_name("ADDRESS:PORT")
+                    .value_parser(clap::value_parser!(SocketAddr))
+                    .conflicts_with_all(["pcap", "infile"])
+                    .help("Receive UDP datagrams on an address like 0.0.0.0:3000, each is a message"),
+            )
+            .arg(
+                Arg::new("join")
+                    .long("join")
+                    .value_name("GROUP")
+                    .action(ArgAction::Append)
+                    .value_parser(clap::value_parser!(IpAddr))
+                    .requires("udp")
+                    .help("Join a multicast group to receive its datagrams with --udp"),
+            )
+            .group(ArgGroup::new("packet input").args(["pcap", "udp"]))
             .arg(
                 Arg::new("chunksize (bytes)")
                     .short('s')
@@ -139,6 +323,54 @@ impl Args {
                     .action(ArgAction::SetTrue)
                     .help("Print raw ascii of the chunk at top of output"),
             )
+            .arg(
+                Arg::new("strip headers")
+                    .long("strip-headers")
+                    .action(ArgAction::SetTrue)
+                    .requires("pcap")
+                    .help("Apply the mask to the UDP or TCP payload of each packet")
+                    .long_help("Apply the mask to the UDP or TCP payload of \
+                                each packet. The headers of the link layer \
+                                (Ethernet with VLAN tags, Linux cooked capture, \
+                                raw IP, loopback), of IPv4 or IPv6 and of UDP or \
+                                TCP are skipped, so no --offset is needed. \
+                                Packets that are no UDP or TCP are left out."),
+            )
+            .arg(
+                Arg::new("print addresses")
+                    .long("addresses")
+                    .action(ArgAction::SetTrue)
+                    .requires("packet input")
+                    .help("Print the protocol, addresses and ports of each packet"),
+            )
+            .arg(
+                Arg::new("filter")
+                    .long("filter")
+                    .requires("packet input")
+                    .value_parser(parse_filter)
+                    .help("Only show packets that match a filter like 'udp and dst port 17224'")
+                    .long_help("Only show packets that match a filter, like \
+                                'udp and dst port 17224 and src host 10.0.0.5'. \
+                                There are udp, tcp, ip, ip6, [src|dst] host \
+                                ADDRESS, [src|dst] net ADDRESS/PREFIX and \
+                                [src|dst] port PORT, combined with and, or, \
+                                not and parentheses. Packets that are no UDP \
+                                or TCP never match."),
+            )
+            .arg(
+                Arg::new("pcap buffer (bytes)")
+                    .long("pcap-buffer")
+                    .requires("pcap")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Largest size of the read buffer for pcap input, in bytes")
+                    .long_help("Largest size of the read buffer for pcap input, \
+                                in bytes. The buffer grows to the snaplen of the \
+                                capture and for larger records up to this size. \
+                                A record that does not fit is skipped as a whole, \
+                                reported with its size and counted apart from \
+                                corrupt data, mview then exits with code 2. \
+                                Default is 16 MiB."),
+            )
             .arg(
                 Arg::new("pause (ms)")
                     .long("pause")
@@ -150,6 +382,7 @@ impl Args {
                 Arg::new("little endian")
 					.long("little-endian")
 					.visible_aliases(["le", "littleendian"])
+                    .global(true)
                     .action(ArgAction::SetTrue)
                     .help("Interpret integers as little endian (default is big endian)."),
             )
@@ -166,6 +399,31 @@ impl Args {
                     .value_parser(clap::value_parser!(usize))
                     .help("Read only the first x bytes where x is the number given, print that as a message and then exit."),
             )
+            .arg(
+                Arg::new("stream")
+                    .long("stream")
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with("pcap")
+                    .help("Read raw input as a stream of records of the size of a chunk")
+                    .long_help("Read raw input as a stream of records of the \
+                                size of a chunk, each record is a message. \
+                                Bytes of a record that is not complete yet are \
+                                kept for the next read, so the records of large \
+                                files and pipes stay aligned no matter how the \
+                                input is delivered. The size is --chunksize or \
+                                the size of the config."),
+            )
+            .arg(
+                Arg::new("follow")
+                    .long("follow")
+                    .action(ArgAction::SetTrue)
+                    .requires("infile")
+                    .help("Wait for more data at the end of the input file, like tail -f")
+                    .long_help("Wait for more data at the end of the input \
+                                file, like tail -f, for raw and pcap input. When \
+                                the file is truncated or replaced by a new one, \
+                                like by a rotation, it is read from the start."),
+            )
             .arg(
                 Arg::new("print statistics")
                     .long("stats")
@@ -178,6 +436,16 @@ impl Args {
                     .action(ArgAction::SetTrue)
                     .help("Print the current position inside a chunk. (For debugging purposes)"),
             )
+            .arg(
+                Arg::new("layout")
+                    .long("layout")
+                    .action(ArgAction::SetTrue)
+                    .help("Print the offset and size of each field of the config and exit")
+                    .long_help("Print the offset and size of each field of \
+                                the config and exit, without reading any data. \
+                                Warns about fields that overlap and fields \
+                                that do not fit into --chunksize."),
+            )
             .arg(
                 Arg::new("no cursor jumping")
                     .long("nojump")
@@ -212,14 +480,78 @@ impl Args {
                                 To avoid making a mess this argument lets \
                                 mview filter the strings from newline \
                                 characters"),
-            )
+            );
+        NEGATED_FLAGS
+            .iter()
+            .fold(command, |command, (id, flag, negation)| {
+                command.arg(
+                    Arg::new(*negation)
+                        .long(*negation)
+                        .action(ArgAction::SetTrue)
+                        .overrides_with(*id)
+                        .help(format!("Switch off --{} of a profile or the config", flag)),
+                )
+            })
     }
     pub fn parse() -> Self {
-        let matches = Args::command().get_matches();
+        Self::parse_from(std::env::args_os())
+    }
+    pub fn parse_from<I, T>(argv: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let mut argv: Vec<OsString> = argv.into_iter().map(|arg| arg.into()).collect();
+        let mut matches = Args::command().get_matches_from(argv.clone());
+        // the options of a profile go before the ones of the command line
+        if let Some(profile) = matches.get_one::<String>("profile") {
+            let options = read_profile(profile).unwrap_or_else(|e| {
+                Args::command()
+                    .error(ErrorKind::InvalidValue, format!("{:#}", e))
+                    .exit()
+            });
+            argv.splice(1..1, options.into_iter().map(OsString::from));
+            matches = Args::command().get_matches_from(argv.clone());
+        }
+        // the options in the header of the config go before the ones of the
+        // profile and the command line, so these override them
+        if matches.subcommand().is_none() {
+            // a config that can't be read is reported when the mask is read
+            let options = match matches.get_one::<String>("config") {
+                Some(config) if Path::new(config).is_file() => {
+                    config_options(config).unwrap_or_else(|e| {
+                        Args::command()
+                            .error(ErrorKind::InvalidValue, format!("{:#}", e))
+                            .exit()
+                    })
+                }
+                _ => Vec::new(),
+            };
+            if !options.is_empty() {
+                argv.splice(1..1, options.into_iter().map(OsString::from));
+                matches = Args::command().get_matches_from(argv);
+            }
+        }
 
         let infile = matches.get_one::<String>("infile").cloned().unwrap_or_default();
         let outfile = matches.get_one::<String>("outfile").cloned().unwrap_or_default();
         let config = matches.get_one::<String>("config").cloned().unwrap_or_default();
+        // -f and --mask in the order they are given
+        let mut mask_lines: Vec<(usize, String)> = Vec::new();
+        if let (Some(indices), Some(lines)) =
+            (matches.indices_of("field"), matches.get_many::<String>("field"))
+        {
+            mask_lines.extend(indices.zip(lines.cloned()));
+        }
+        if let (Some(indices), Some(masks)) =
+            (matches.indices_of("mask"), matches.get_many::<String>("mask"))
+        {
+            for (index, mask) in indices.zip(masks) {
+                mask_lines.extend(split_mask(mask).into_iter().map(|line| (index, line)));
+            }
+        }
+        mask_lines.sort_by_key(|(index, _)| *index);
+        let mask_lines = mask_lines.into_iter().map(|(_, line)| line).collect();
         let pcap = matches.get_flag("pcap");
         let chunksize = matches
             .try_get_one::<usize>("chunksize (bytes)")
@@ -246,15 +578,73 @@ impl Args {
             .try_get_one::<usize>("head (bytes)")
             .unwrap_or_default()
             .unwrap_or(&0);
+        let stream = matches.get_flag("stream");
+        let follow = matches.get_flag("follow");
+        let udp = matches.get_one::<SocketAddr>("udp").copied();
+        let join = matches
+            .get_many::<IpAddr>("join")
+            .map(|groups| groups.copied().collect())
+            .unwrap_or_default();
         let print_statistics = matches.get_flag("print statistics");
         let print_bitpos = matches.get_flag("print bitposition");
+        let layout = matches.get_flag("layout");
+        let strip_headers = matches.get_flag("strip headers");
+        let print_addresses = matches.get_flag("print addresses");
+        let filter = matches.get_one::<Filter>("filter").cloned();
+        let pcap_buffer = matches
+            .try_get_one::<usize>("pcap buffer (bytes)")
+            .unwrap_or_default()
+            .unwrap_or(&MAX_PCAP_BUFFER);
         let cursor_jump = !matches.get_flag("no cursor jumping");
         let clear = matches.get_flag("clear");
         let filter_newlines = matches.get_flag("filter newlines");
+        let subcommand = match matches.subcommand() {
+            Some(("convert", sub)) => Some(Subcommand::Convert {
+                infile: sub.get_one::<String>("input").cloned().unwrap_or_default(),
+                outfile: sub.get_one::<String>("output").cloned().unwrap_or_default(),
+            }),
+            Some(("import-c", sub)) => Some(Subcommand::ImportC {
+                header: sub.get_one::<String>("header").cloned().unwrap_or_default(),
+                outfile: sub.get_one::<String>("output").cloned().unwrap_or_default(),
+            }),
+            Some(("export", sub)) => {
+                let lang = match sub.get_flag("wireshark-lua") {
+                    true => Lang::Lua,
+                    false => sub
+                        .get_one::<String>("lang")
+                        .and_then(|lang| Lang::from_name(lang))
+                        .unwrap_or(Lang::C),
+                };
+                let udp_port = sub.get_one::<u16>("udp-port").copied();
+                // only the dissector is registered for a port
+                if udp_port.is_some() && lang != Lang::Lua {
+                    Args::command()
+                        .error(
+                            ErrorKind::ArgumentConflict,
+                            "--udp-port only applies to the Wireshark dissector of --lang lua",
+                        )
+                        .exit()
+                }
+                Some(Subcommand::Export {
+                    lang,
+                    udp_port,
+                    config: sub.get_one::<String>("config").cloned().unwrap_or_default(),
+                    outfile: sub.get_one::<String>("output").cloned().unwrap_or_default(),
+                })
+            }
+            Some(("layout", sub)) => Some(Subcommand::Layout {
+                config: sub.get_one::<String>("config").cloned().unwrap_or_default(),
+                diagram: sub.get_flag("diagram"),
+                svg: sub.get_one::<String>("svg").cloned().unwrap_or_default(),
+            }),
+            _ => None,
+        };
+        let select = matches.get_one::<String>("struct").cloned().unwrap_or_default();
         Self {
             infile,
             outfile,
             config,
+            mask_lines,
             pcap,
             chunksize: *chunksize,
             offset: *offset,
@@ -266,11 +656,92 @@ impl Args {
             little_endian,
             timestamp,
             read_head: *read_head,
+            stream,
+            follow,
+            udp,
+            join,
             print_statistics,
             print_bitpos,
+            layout,
+            strip_headers,
+            print_addresses,
+            filter,
+            pcap_buffer: *pcap_buffer,
             cursor_jump,
             clear,
             filter_newlines,
+            select,
+            subcommand,
+        }
+    }
+}
+
+// split the lines of --mask at commas, but not at the ones in parentheses
+fn split_mask(mask: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut depth = 0;
+    for c in mask.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                lines.push(line.trim().to_owned());
+                line.clear();
+                continue;
+            }
+            _ => {}
         }
+        line.push(c);
+    }
+    lines.push(line.trim().to_owned());
+    lines.retain(|line| !line.is_empty());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negated_flags() {
+        // the last of a flag and its negation wins
+        let args = Args::parse_from(["mview", "-f", "A:u8", "--pcap", "--le", "--no-pcap"]);
+        assert!(!args.pcap && args.little_endian);
+        let args = Args::parse_from(["mview", "-f", "A:u8", "--no-pcap", "--pcap"]);
+        assert!(args.pcap);
+        let args = Args::parse_from(["mview", "-f", "A:u8", "--nojump", "--jump"]);
+        assert!(args.cursor_jump);
+    }
+
+    #[test]
+    fn test_precedence() {
+        // the config, then the profile, then the command line
+        let dir = std::env::temp_dir()
+            .join(format!("mview_test_precedence_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("mview")).unwrap();
+        let config = dir.join("test.conf");
+        std::fs::write(&config, "#!mview --pcap --offset 1 --stats\nLen:u16\n").unwrap();
+        std::fs::write(
+            dir.join("mview").join("config.toml"),
+            format!(
+                "[profiles.p]\nconfig = {:?}\npcap = false\nlittle_endian = true\noffset = 2\n",
+                config.to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", &dir);
+        let args = Args::parse_from(["mview", "--profile", "p", "--no-little-endian"]);
+        assert!(!args.pcap && args.print_statistics && !args.little_endian);
+        assert_eq!(args.offset, 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_mask() {
+        assert_eq!(
+            split_mask("Len:u16, Name:string:8,State:u8:enum(0=Off,1=On),"),
+            vec!["Len:u16", "Name:string:8", "State:u8:enum(0=Off,1=On)"]
+        );
     }
 }
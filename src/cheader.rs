@@ -0,0 +1,1049 @@
+use crate::{
+    expr::{eval, parse_expr, Scope, Value},
+    mask::MAX_REPETITIONS,
+};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+// Import of masks from C struct definitions. The header is read with a small
+// parser that knows declarations of structs, unions, enums and typedefs, the
+// rest of the header is skipped. The struct is laid out like GCC does it
+// (alignment, packing, bitfield storage units) and written as config lines
+// with explicit gaps for the padding.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Punct(char),
+    Pack(PackOp),
+}
+
+// #pragma pack(...)
+#[derive(Debug, Clone, PartialEq)]
+enum PackOp {
+    Push(Option<usize>),
+    Pop,
+    Set(Option<usize>),
+}
+
+#[derive(Debug, Clone)]
+enum CType {
+    Int(usize, bool), // bits, signed
+    Char,
+    Bool,
+    Float(usize),
+    Pointer,
+    Enum(Vec<(i128, String)>),
+    Record(Box<Record>),
+    Array(Box<CType>, usize),
+    Opaque(String), // void or a struct that is declared but not defined
+}
+
+#[derive(Debug, Clone, Default)]
+struct Record {
+    union: bool,
+    packed: bool,         // __attribute__((packed))
+    pack: Option<usize>,  // #pragma pack(n) at the definition
+    align: Option<usize>, // __attribute__((aligned(n)))
+    members: Vec<Member>,
+}
+
+#[derive(Debug, Clone)]
+struct Member {
+    name: Option<String>, // None for anonymous structs and unions and unnamed bitfields
+    ctype: CType,
+    dims: Vec<usize>,
+    bits: Option<usize>,
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("//") {
+            rest = after.find('\n').map_or("", |end| &after[end..]);
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = after.find("*/").map_or("", |end| &after[end + 2..]);
+            out.push(' ');
+        } else {
+            let c = rest.chars().next().unwrap(); // rest is not empty
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out.replace("\\\n", " ")
+}
+
+fn parse_pack(args: &str) -> Option<PackOp> {
+    let args: Vec<&str> = args.split(',').map(|a| a.trim()).collect();
+    // pack(0) is the default packing, like no number
+    let number = |s: &str| s.parse::<usize>().ok().filter(|&n| n > 0);
+    match args.as_slice() {
+        ["push"] => Some(PackOp::Push(None)),
+        ["push", n] | ["push", _, n] => Some(PackOp::Push(number(n))),
+        ["pop", ..] => Some(PackOp::Pop),
+        [""] => Some(PackOp::Set(None)),
+        [n] => Some(PackOp::Set(number(n))),
+        _ => None,
+    }
+}
+
+fn tokenize(text: &str, defines: &mut HashMap<String, String>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for line in strip_comments(text).lines() {
+        let line = line.trim();
+        if let Some(directive) = line.strip_prefix('#') {
+            let directive = directive.trim_start();
+            if let Some(define) = directive.strip_prefix("define") {
+                // only object like macros, they can be array sizes or enum values
+                let define = define.trim();
+                let name_len = define
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(define.len());
+                if !define[name_len..].starts_with('(') {
+                    defines.insert(
+                        define[..name_len].to_owned(),
+                        define[name_len..].trim().to_owned(),
+                    );
+                }
+            } else if let Some(pragma) = directive.strip_prefix("pragma") {
+                let pragma: String = pragma.split_whitespace().collect();
+                if let Some(args) = pragma
+                    .strip_prefix("pack(")
+                    .and_then(|p| p.strip_suffix(')'))
+                {
+                    tokens.extend(parse_pack(args).map(Token::Pack));
+                }
+            }
+            continue;
+        }
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let mut word = &rest[..len];
+                if c.is_ascii_digit() {
+                    // 4U, 16UL
+                    word = word.trim_end_matches(['u', 'U', 'l', 'L']);
+                }
+                tokens.push(Token::Word(word.to_owned()));
+                rest = &rest[len..];
+            } else {
+                if !c.is_whitespace() {
+                    tokens.push(Token::Punct(c));
+                }
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    tokens
+}
+
+fn builtin_type(name: &str) -> Option<CType> {
+    Some(match name {
+        "uint8_t" | "u_int8_t" | "__u8" => CType::Int(8, false),
+        "uint16_t" | "u_int16_t" | "__u16" => CType::Int(16, false),
+        "uint32_t" | "u_int32_t" | "__u32" => CType::Int(32, false),
+        "uint64_t" | "u_int64_t" | "__u64" | "size_t" | "uintptr_t" => CType::Int(64, false),
+        "int8_t" | "__s8" => CType::Int(8, true),
+        "int16_t" | "__s16" => CType::Int(16, true),
+        "int32_t" | "__s32" => CType::Int(32, true),
+        "int64_t" | "__s64" | "ssize_t" | "intptr_t" | "ptrdiff_t" => CType::Int(64, true),
+        "__int128_t" => CType::Int(128, true),
+        "__uint128_t" => CType::Int(128, false),
+        "bool" | "_Bool" => CType::Bool,
+        "float" => CType::Float(32),
+        "double" => CType::Float(64),
+        "void" => CType::Opaque("void".to_owned()),
+        _ => return None,
+    })
+}
+
+// words that can be part of a type but do not change its layout
+const QUALIFIERS: [&str; 8] = [
+    "const",
+    "volatile",
+    "static",
+    "extern",
+    "inline",
+    "register",
+    "__extension__",
+    "restrict",
+];
+const INT_WORDS: [&str; 6] = ["signed", "unsigned", "short", "long", "int", "char"];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    pack: Option<usize>,
+    pack_stack: Vec<Option<usize>>,
+    constants: Scope, // defines and enum constants
+    defines: HashMap<String, String>,
+    typedefs: HashMap<String, CType>,
+    tags: HashMap<String, CType>,        // struct, union and enum tags
+    records: Vec<(Vec<String>, Record)>, // named structs and unions in the order of the header
+    last_record: Option<usize>,          // index in records of the struct read last
+    errors: Vec<String>,                 // declarations that could not be read
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn peek_word(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+    fn expect(&mut self, c: char) -> Result<()> {
+        if !self.is_punct(c) {
+            bail!("Expected '{}' but found {}.", c, self.describe());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        match self.peek() {
+            Some(Token::Word(word)) => format!("\"{}\"", word),
+            Some(Token::Punct(c)) => format!("'{}'", c),
+            Some(Token::Pack(_)) => "#pragma pack".to_owned(),
+            None => "the end of the header".to_owned(),
+        }
+    }
+    fn apply_pack(&mut self, op: PackOp) {
+        match op {
+            PackOp::Push(n) => {
+                self.pack_stack.push(self.pack);
+                if n.is_some() {
+                    self.pack = n;
+                }
+            }
+            PackOp::Pop => self.pack = self.pack_stack.pop().flatten(),
+            PackOp::Set(n) => self.pack = n,
+        }
+    }
+
+    // skip a declaration that is of no interest, like a function
+    fn skip_declaration(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek().cloned() {
+            self.pos += 1;
+            match token {
+                Token::Punct('{') | Token::Punct('(') | Token::Punct('[') => depth += 1,
+                Token::Punct('}') | Token::Punct(')') | Token::Punct(']') => {
+                    depth -= 1;
+                    // end of a function body
+                    if depth == 0 && token == Token::Punct('}') && !self.is_punct(';') {
+                        return;
+                    }
+                }
+                Token::Punct(';') if depth <= 0 => return,
+                _ => {}
+            }
+        }
+    }
+
+    // skip ( ... ) with everything inside
+    fn skip_brackets(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct('(') => depth += 1,
+                Token::Punct(')') => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+            if depth == 0 {
+                return;
+            }
+        }
+    }
+
+    // tokens up to one of the stop characters (on the same bracket level), as an expression
+    fn constant(&mut self, stop: &[char]) -> Result<i128> {
+        let mut text = String::new();
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct(c) if depth == 0 && stop.contains(c) => break,
+                Token::Punct(c) => {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    text.push(*c);
+                }
+                Token::Word(word) => {
+                    if text.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+                        text.push(' ');
+                    }
+                    text.push_str(word);
+                }
+                Token::Pack(_) => {}
+            }
+            self.pos += 1;
+        }
+        self.eval_constant(&text, 0)
+    }
+    fn eval_constant(&self, text: &str, depth: usize) -> Result<i128> {
+        let expr = parse_expr(text).with_context(|| format!("Can not read \"{}\".", text))?;
+        let mut scope = self.constants.clone();
+        // macros can refer to other macros
+        for word in text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_')) {
+            if let Some(value) = self.defines.get(word) {
+                if depth < 16 && !scope.contains_key(word) {
+                    let value = self.eval_constant(value, depth + 1)?;
+                    scope.insert(word.to_owned(), Value::Int(value));
+                }
+            }
+        }
+        match eval(&expr, &scope).with_context(|| format!("Can not evaluate \"{}\".", text))? {
+            Value::Int(i) => Ok(i),
+            Value::Float(_) => bail!("\"{}\" is not an integer.", text),
+        }
+    }
+
+    // the n of aligned(n) or alignas(n)
+    fn alignment(&mut self) -> Result<usize> {
+        let value = self.constant(&[')'])?;
+        match usize::try_from(value) {
+            Ok(align) if align.is_power_of_two() => Ok(align),
+            _ => bail!("Alignment {} is not a positive power of 2.", value),
+        }
+    }
+
+    // __attribute__((packed, aligned(4))), the attributes that matter are set on the record
+    fn attributes(&mut self, record: &mut Record) -> Result<()> {
+        loop {
+            match self.peek_word() {
+                Some("__attribute__") | Some("__attribute") => {
+                    self.pos += 1;
+                    self.expect('(')?;
+                    self.expect('(')?;
+                    while !self.is_punct(')') {
+                        let name = self.peek_word().unwrap_or_default().trim_matches('_');
+                        let name = name.to_owned();
+                        self.pos += 1;
+                        if self.is_punct('(') && name == "aligned" {
+                            self.pos += 1;
+                            record.align = Some(self.alignment()?);
+                            self.expect(')')?;
+                        } else if self.is_punct('(') {
+                            self.skip_brackets();
+                        } else if name == "aligned" {
+                            record.align = Some(16); // the largest alignment of the machine
+                        }
+                        if name == "packed" {
+                            record.packed = true;
+                        }
+                        if self.is_punct(',') {
+                            self.pos += 1;
+                        }
+                        if self.peek().is_none() {
+                            bail!("Missing ) in __attribute__.");
+                        }
+                    }
+                    self.expect(')')?;
+                    self.expect(')')?;
+                }
+                Some("__packed") => {
+                    self.pos += 1;
+                    record.packed = true;
+                }
+                Some("alignas") | Some("_Alignas") => {
+                    self.pos += 1;
+                    self.expect('(')?;
+                    let value = self.alignment()?;
+                    self.expect(')')?;
+                    record.align = Some(value);
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn enum_body(&mut self) -> Result<Vec<(i128, String)>> {
+        self.expect('{')?;
+        let mut names = Vec::new();
+        let mut next = 0;
+        while !self.is_punct('}') {
+            let name = self
+                .peek_word()
+                .with_context(|| format!("Expected an enum constant, found {}.", self.describe()))?
+                .to_owned();
+            self.pos += 1;
+            if self.is_punct('=') {
+                self.pos += 1;
+                next = self.constant(&[',', '}'])?;
+            }
+            self.constants.insert(name.clone(), Value::Int(next));
+            names.push((next, name));
+            next += 1;
+            if self.is_punct(',') {
+                self.pos += 1;
+            }
+        }
+        self.expect('}')?;
+        Ok(names)
+    }
+
+    // struct or union, the keyword is already read
+    fn record(&mut self, union: bool) -> Result<CType> {
+        let mut record = Record {
+            union,
+            pack: self.pack,
+            ..Default::default()
+        };
+        self.attributes(&mut record)?;
+        let tag = self.peek_word().map(|t| t.to_owned());
+        if tag.is_some() {
+            self.pos += 1;
+        }
+        self.attributes(&mut record)?;
+        if !self.is_punct('{') {
+            let tag = tag.context("Expected the name or the body of a struct.")?;
+            self.last_record = self
+                .records
+                .iter()
+                .position(|(names, _)| names.contains(&tag));
+            return Ok(self
+                .tags
+                .get(&tag)
+                .cloned()
+                .unwrap_or(CType::Opaque(format!("struct {}", tag))));
+        }
+        self.pos += 1;
+        while !self.is_punct('}') {
+            if let Some(Token::Pack(op)) = self.peek().cloned() {
+                self.pos += 1;
+                self.apply_pack(op);
+                continue;
+            }
+            self.members(&mut record)?;
+        }
+        self.pos += 1;
+        self.attributes(&mut record)?;
+        let ctype = CType::Record(Box::new(record.clone()));
+        self.last_record = None;
+        if let Some(tag) = tag {
+            self.tags.insert(tag.clone(), ctype.clone());
+            self.records.push((vec![tag], record));
+            self.last_record = Some(self.records.len() - 1);
+        }
+        Ok(ctype)
+    }
+
+    // the type before the declarators, like const unsigned long or struct foo { ... }
+    fn type_specifier(&mut self) -> Result<CType> {
+        let mut int_words: Vec<String> = Vec::new();
+        let mut ctype = None;
+        let mut ignored = Record::default();
+        while let Some(word) = self.peek_word().map(|w| w.to_owned()) {
+            if QUALIFIERS.contains(&word.as_str()) {
+                self.pos += 1;
+            } else if word.starts_with("__attribute") {
+                self.attributes(&mut ignored)?;
+            } else if INT_WORDS.contains(&word.as_str()) && ctype.is_none() {
+                self.pos += 1;
+                int_words.push(word);
+            } else if ctype.is_some() || !int_words.is_empty() {
+                break; // the name of the declarator
+            } else if word == "struct" || word == "union" {
+                self.pos += 1;
+                ctype = Some(self.record(word == "union")?);
+            } else if word == "enum" {
+                self.pos += 1;
+                let tag = self.peek_word().map(|t| t.to_owned());
+                if tag.is_some() {
+                    self.pos += 1;
+                }
+                let enum_type = if self.is_punct('{') {
+                    CType::Enum(self.enum_body()?)
+                } else {
+                    let tag = tag
+                        .clone()
+                        .context("Expected the name or the body of an enum.")?;
+                    self.tags
+                        .get(&tag)
+                        .cloned()
+                        .with_context(|| format!("enum {} is used before it is defined.", tag))?
+                };
+                if let Some(tag) = tag {
+                    self.tags.insert(tag, enum_type.clone());
+                }
+                ctype = Some(enum_type);
+            } else if let Some(builtin) = builtin_type(&word) {
+                self.pos += 1;
+                ctype = Some(builtin);
+            } else if let Some(typedef) = self.typedefs.get(&word) {
+                ctype = Some(typedef.clone());
+                self.pos += 1;
+            } else {
+                bail!("Unknown type \"{}\".", word);
+            }
+        }
+        if let Some(ctype) = ctype {
+            return Ok(ctype);
+        }
+        let has = |w: &str| int_words.iter().any(|i| i == w);
+        let signed = !has("unsigned");
+        Ok(if has("char") {
+            if has("signed") || has("unsigned") {
+                CType::Int(8, signed)
+            } else {
+                CType::Char
+            }
+        } else if has("short") {
+            CType::Int(16, signed)
+        } else if has("long") {
+            CType::Int(64, signed) // LP64, like Linux on 64 bit machines
+        } else if !int_words.is_empty() {
+            CType::Int(32, signed)
+        } else {
+            bail!("Expected a type, found {}.", self.describe());
+        })
+    }
+
+    // *name[2][3] : 4
+    fn declarator(&mut self, ctype: &CType) -> Result<Member> {
+        let mut ctype = ctype.clone();
+        let mut ignored = Record::default();
+        while self.is_punct('*') {
+            self.pos += 1;
+            ctype = CType::Pointer;
+            while matches!(self.peek_word(), Some(w) if QUALIFIERS.contains(&w)) {
+                self.pos += 1;
+            }
+        }
+        let name = self.peek_word().map(|n| n.to_owned());
+        if name.is_some() {
+            self.pos += 1;
+        }
+        let mut dims = Vec::new();
+        while self.is_punct('[') {
+            self.pos += 1;
+            let dim = self.constant(&[']'])?;
+            match usize::try_from(dim) {
+                Ok(dim) if dim > 0 => dims.push(dim),
+                _ => bail!("Invalid array size {}.", dim),
+            }
+            self.expect(']')?;
+        }
+        let mut bits = None;
+        if self.is_punct(':') {
+            self.pos += 1;
+            let width = self.constant(&[',', ';'])?;
+            let width = usize::try_from(width)
+                .with_context(|| format!("Invalid bitfield width {}.", width))?;
+            bits = Some(width);
+        }
+        self.attributes(&mut ignored)?;
+        Ok(Member {
+            name,
+            ctype,
+            dims,
+            bits,
+        })
+    }
+
+    fn members(&mut self, record: &mut Record) -> Result<()> {
+        let records_before = self.records.len();
+        let ctype = self.type_specifier()?;
+        // struct foo { ... }; without a name only defines struct foo, struct { ... };
+        // is an anonymous member whose members belong to the outer struct
+        let tagged = self.records.len() > records_before;
+        loop {
+            let member = self.declarator(&ctype)?;
+            let definition_only = member.name.is_none()
+                && member.bits.is_none()
+                && (tagged || !matches!(&member.ctype, CType::Record(_)));
+            if !definition_only {
+                record.members.push(member);
+            }
+            if self.is_punct(',') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.expect(';')
+    }
+
+    fn typedef(&mut self) -> Result<()> {
+        self.last_record = None;
+        let ctype = self.type_specifier()?;
+        loop {
+            let member = self.declarator(&ctype)?;
+            let name = member.name.context("typedef without a name.")?;
+            let mut ctype = member.ctype;
+            for dim in member.dims.into_iter().rev() {
+                ctype = CType::Array(Box::new(ctype), dim);
+            }
+            if let CType::Record(record) = &ctype {
+                // typedef struct foo { ... } foo_t; is known by both names
+                match self.last_record {
+                    Some(i) => self.records[i].0.push(name.clone()),
+                    None => {
+                        self.records.push((vec![name.clone()], *record.clone()));
+                        self.last_record = Some(self.records.len() - 1);
+                    }
+                }
+            }
+            self.typedefs.insert(name, ctype);
+            if self.is_punct(',') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.expect(';')
+    }
+
+    fn header(&mut self) {
+        while let Some(token) = self.peek().cloned() {
+            let start = self.pos;
+            let result = match token {
+                Token::Pack(op) => {
+                    self.pos += 1;
+                    self.apply_pack(op);
+                    Ok(())
+                }
+                // ; and the } of extern "C" { ... }
+                Token::Punct(';') | Token::Punct('}') => {
+                    self.pos += 1;
+                    Ok(())
+                }
+                Token::Word(word)
+                    if word == "extern"
+                        && self.tokens.get(start + 1) == Some(&Token::Punct('"')) =>
+                {
+                    self.pos += 4; // extern " C "
+                    if self.is_punct('{') {
+                        self.pos += 1;
+                    }
+                    Ok(())
+                }
+                Token::Word(word) if word == "typedef" => {
+                    self.pos += 1;
+                    self.typedef()
+                }
+                Token::Word(word) if ["struct", "union", "enum"].contains(&word.as_str()) => {
+                    // struct foo { ... } variable; declares a variable as well
+                    self.type_specifier().map(|_| self.skip_declaration())
+                }
+                _ => {
+                    self.skip_declaration();
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                // the rest of the header can still be of use
+                self.errors.push(format!("{:#}", err));
+                self.pos = start;
+                self.skip_declaration();
+            }
+        }
+    }
+}
+
+fn align_up(pos: usize, alignment: usize) -> usize {
+    pos.div_ceil(alignment) * alignment
+}
+
+// writes the config lines of C types, the positions are in bits
+struct Emitter {
+    little_endian: bool,
+}
+
+impl Emitter {
+    fn order(&self) -> &'static str {
+        if self.little_endian {
+            "le"
+        } else {
+            "be"
+        }
+    }
+
+    // size and alignment in bytes
+    fn size_align(&self, ctype: &CType) -> Result<(usize, usize)> {
+        Ok(match ctype {
+            CType::Int(bits, _) | CType::Float(bits) => (bits / 8, bits / 8),
+            CType::Char | CType::Bool => (1, 1),
+            CType::Pointer => (8, 8),
+            CType::Enum(_) => (4, 4),
+            CType::Record(record) => self.record(record, "", &mut Vec::new())?,
+            CType::Array(element, n) => {
+                let (size, align) = self.size_align(element)?;
+                (
+                    size.checked_mul(*n).context("An array is too large.")?,
+                    align,
+                )
+            }
+            CType::Opaque(name) => bail!("The size of {} is not known.", name),
+        })
+    }
+
+    fn gap(lines: &mut Vec<String>, from: usize, to: usize) {
+        if to > from {
+            if from.is_multiple_of(8) && to.is_multiple_of(8) {
+                lines.push(format!("padding:bytegap:{}", (to - from) / 8));
+            } else {
+                lines.push(format!("padding:bitgap:{}", to - from));
+            }
+        }
+    }
+
+    fn enum_attribute(names: &[(i128, String)]) -> String {
+        let names: Vec<String> = names.iter().map(|(v, n)| format!("{}={}", v, n)).collect();
+        format!("enum({})", names.join(","))
+    }
+
+    fn bitfield(&self, name: &str, ctype: &CType, width: usize) -> Result<String> {
+        Ok(match ctype {
+            CType::Bool if width == 1 => format!("{}:bool1:{}", name, self.order()),
+            CType::Int(_, true) | CType::Char => {
+                format!("{}:iarb:{}:{}", name, width, self.order())
+            }
+            CType::Int(_, false) | CType::Bool => {
+                format!("{}:uarb:{}:{}", name, width, self.order())
+            }
+            CType::Enum(names) => format!(
+                "{}:uarb:{}:{}:{}",
+                name,
+                width,
+                self.order(),
+                Emitter::enum_attribute(names)
+            ),
+            _ => bail!("Bitfield {} does not have an integer type.", name),
+        })
+    }
+
+    fn member(
+        &self,
+        name: &str,
+        ctype: &CType,
+        dims: &[usize],
+        lines: &mut Vec<String>,
+    ) -> Result<()> {
+        if let Some((n, inner_dims)) = dims.split_first() {
+            if matches!(ctype, CType::Char) && inner_dims.is_empty() {
+                lines.push(format!("{}:string:{}", name, n));
+                return Ok(());
+            }
+            // every element is a config line of its own
+            let elements = dims
+                .iter()
+                .try_fold(1usize, |elements, &dim| elements.checked_mul(dim));
+            if elements.is_none_or(|elements| elements as i128 > MAX_REPETITIONS) {
+                bail!("{} has more than {} elements.", name, MAX_REPETITIONS);
+            }
+            for i in 0..*n {
+                // elements that are structs or arrays themselves add up
+                if lines.len() as i128 > MAX_REPETITIONS {
+                    bail!("{} has more than {} config lines.", name, MAX_REPETITIONS);
+                }
+                self.member(&format!("{}[{}]", name, i), ctype, inner_dims, lines)?;
+            }
+            return Ok(());
+        }
+        match ctype {
+            CType::Int(8, signed) => {
+                lines.push(format!("{}:{}8", name, if *signed { "i" } else { "u" }))
+            }
+            CType::Int(bits, signed) => lines.push(format!(
+                "{}:{}{}:{}",
+                name,
+                if *signed { "i" } else { "u" },
+                bits,
+                self.order()
+            )),
+            CType::Char => lines.push(format!("{}:string:1", name)),
+            CType::Bool => lines.push(format!("{}:bool8", name)),
+            CType::Float(bits) => lines.push(format!("{}:f{}:{}", name, bits, self.order())),
+            CType::Pointer => lines.push(format!("{}:u64:hex:{}", name, self.order())),
+            CType::Enum(names) => lines.push(format!(
+                "{}:i32:{}:{}",
+                name,
+                self.order(),
+                Emitter::enum_attribute(names)
+            )),
+            CType::Record(record) => {
+                self.record(record, &format!("{}.", name), lines)?;
+            }
+            CType::Array(element, n) => self.member(name, element, &[*n], lines)?,
+            CType::Opaque(_) => {} // size_align() already failed for it
+        }
+        Ok(())
+    }
+
+    // writes the lines of the members and returns size and alignment of the record in bytes
+    fn record(
+        &self,
+        record: &Record,
+        prefix: &str,
+        lines: &mut Vec<String>,
+    ) -> Result<(usize, usize)> {
+        let mut pos = 0; // bits
+        let mut end = 0; // end of the last written line, for the gaps
+        let mut record_align = 1;
+        if record.union {
+            lines.push("overlay {".to_owned());
+        }
+        for member in record.members.iter() {
+            let (size, natural_align) = self.size_align(&member.ctype)?;
+            let align = if record.packed {
+                1
+            } else {
+                natural_align.min(record.pack.unwrap_or(natural_align))
+            };
+            let name = format!("{}{}", prefix, member.name.as_deref().unwrap_or_default());
+            let mut member_lines = Vec::new();
+            let start = if record.union { 0 } else { pos };
+            let mut member_start = start;
+            let member_bits;
+            match member.bits {
+                Some(width) => {
+                    if width > size * 8 {
+                        bail!("Bitfield {} is wider than its type.", name);
+                    }
+                    if width == 0 {
+                        // starts a new storage unit
+                        if !record.packed {
+                            pos = align_up(pos, natural_align * 8);
+                        }
+                        continue;
+                    }
+                    // a bitfield can not reach over the boundary of its storage unit
+                    if !record.packed && start % (size * 8) + width > size * 8 {
+                        member_start = align_up(start, align * 8);
+                    }
+                    if member.name.is_some() {
+                        record_align = record_align.max(align);
+                        member_lines.push(self.bitfield(&name, &member.ctype, width)?);
+                    } else {
+                        Emitter::gap(&mut member_lines, 0, width);
+                    }
+                    member_bits = width;
+                }
+                None => {
+                    member_bits = member
+                        .dims
+                        .iter()
+                        .try_fold(size, |bytes, &dim| bytes.checked_mul(dim))
+                        .and_then(|bytes| bytes.checked_mul(8))
+                        .with_context(|| format!("{} is too large.", name))?;
+                    member_start = align_up(start, align * 8);
+                    record_align = record_align.max(align);
+                    match (&member.name, &member.ctype) {
+                        (Some(_), ctype) => {
+                            self.member(&name, ctype, &member.dims, &mut member_lines)?
+                        }
+                        // anonymous struct or union, its members belong to this record
+                        (None, CType::Record(inner)) => {
+                            self.record(inner, prefix, &mut member_lines)?;
+                        }
+                        (None, _) => {}
+                    }
+                }
+            }
+            if record.union {
+                // every member of an overlay is a single item
+                if member_lines.len() > 1 || member_start > 0 {
+                    lines.push("struct {".to_owned());
+                    Emitter::gap(lines, 0, member_start);
+                    lines.extend(member_lines);
+                    lines.push("}".to_owned());
+                } else {
+                    lines.extend(member_lines);
+                }
+                end = end.max(member_start + member_bits);
+            } else {
+                Emitter::gap(lines, end, member_start);
+                lines.extend(member_lines);
+                pos = member_start + member_bits;
+                end = pos;
+            }
+        }
+        if record.union {
+            lines.push("}".to_owned());
+        }
+        if let Some(align) = record.align {
+            record_align = record_align.max(align);
+        }
+        let size = align_up(align_up(end, 8), record_align * 8);
+        Emitter::gap(lines, end, size);
+        Ok((size / 8, record_align))
+    }
+}
+
+fn parse_header(text: &str) -> Parser {
+    let mut defines = HashMap::new();
+    let tokens = tokenize(text, &mut defines);
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        pack: None,
+        pack_stack: Vec::new(),
+        constants: Scope::new(),
+        defines,
+        typedefs: HashMap::new(),
+        tags: HashMap::new(),
+        records: Vec::new(),
+        last_record: None,
+        errors: Vec::new(),
+    };
+    parser.header();
+    parser
+}
+
+// the config lines for a struct of a C header. If the header has only one
+// struct, struct_name can be empty.
+pub fn import_c(text: &str, struct_name: &str, little_endian: bool) -> Result<Vec<String>> {
+    let parser = parse_header(text);
+    // the declarations that were skipped can explain why a struct is missing
+    let skipped = if parser.errors.is_empty() {
+        String::new()
+    } else {
+        format!(" Skipped declarations: {}", parser.errors.join("; "))
+    };
+    let (names, record) = if struct_name.is_empty() {
+        match parser.records.as_slice() {
+            [record] => record,
+            [] => bail!("The header does not define a struct.{}", skipped),
+            records => bail!(
+                "The header defines several structs ({}), choose one with --struct.",
+                records
+                    .iter()
+                    .map(|(names, _)| names.join("/"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    } else {
+        parser
+            .records
+            .iter()
+            .find(|(names, _)| names.iter().any(|n| n == struct_name))
+            .with_context(|| {
+                format!(
+                    "The header does not define struct {}.{}",
+                    struct_name, skipped
+                )
+            })?
+    };
+    let emitter = Emitter { little_endian };
+    let mut lines = vec![format!("# struct {} imported by mview import-c", names[0])];
+    let (size, _) = emitter.record(record, "", &mut lines)?;
+    lines.insert(1, format!("# {} bytes", size));
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "
+#include <stdint.h>
+#define NAME_LEN (4 * 2)
+/* a message */
+typedef enum { MODE_OFF, MODE_ON = 5 } mode_t;
+struct inner { uint8_t a; uint32_t b; };
+typedef struct msg {
+    uint16_t id; // the id
+    uint8_t flags : 3;
+    uint8_t prio : 5;
+    uint32_t big : 20;
+    int8_t small : 4;
+    char name[NAME_LEN];
+    mode_t mode;
+    struct inner in;
+    union { uint32_t raw; float f; };
+    bool ok;
+} msg_t;
+#pragma pack(push, 1)
+struct packed_msg { uint8_t a; uint32_t b; uint16_t c[2]; };
+#pragma pack(pop)
+struct __attribute__((packed, deprecated(\"old\"))) other { uint8_t a; unsigned short b; };
+int func(int a) { return a; }
+";
+
+    fn import(struct_name: &str, little_endian: bool) -> Vec<String> {
+        // without the two comment lines at the top
+        import_c(HEADER, struct_name, little_endian).unwrap()[2..].to_vec()
+    }
+
+    #[test]
+    fn test_import_c_layout() {
+        // offsets as GCC lays the struct out on x86_64
+        assert_eq!(
+            import("msg_t", true),
+            vec![
+                "id:u16:le",
+                "flags:uarb:3:le",
+                "prio:uarb:5:le",
+                "padding:bytegap:1",
+                "big:uarb:20:le",
+                "small:iarb:4:le",
+                "name:string:8",
+                "padding:bytegap:1",
+                "mode:i32:le:enum(0=MODE_OFF,5=MODE_ON)",
+                "in.a:u8",
+                "padding:bytegap:3",
+                "in.b:u32:le",
+                "overlay {",
+                "raw:u32:le",
+                "f:f32:le",
+                "}",
+                "ok:bool8",
+                "padding:bytegap:3",
+            ]
+        );
+        assert_eq!(import_c(HEADER, "msg", true).unwrap()[1], "# 36 bytes");
+    }
+    #[test]
+    fn test_import_c_packed() {
+        assert_eq!(
+            import("packed_msg", false),
+            vec!["a:u8", "b:u32:be", "c[0]:u16:be", "c[1]:u16:be"]
+        );
+        assert_eq!(import("other", false), vec!["a:u8", "b:u16:be"]);
+        // pack(0) is the default packing
+        let header = "#pragma pack(1)\n#pragma pack(0)\nstruct p { char a; int b; };";
+        assert_eq!(
+            import_c(header, "", false).unwrap()[2..],
+            ["a:string:1", "padding:bytegap:3", "b:i32:be"]
+        );
+        assert_eq!(
+            import("inner", false),
+            vec!["a:u8", "padding:bytegap:3", "b:u32:be"]
+        );
+    }
+    #[test]
+    fn test_import_c_errors() {
+        assert!(import_c(HEADER, "", false).is_err()); // several structs
+        assert!(import_c(HEADER, "missing", false).is_err());
+        let header = "struct a { unknown_t x; };\nstruct b { uint8_t y; };";
+        // struct a is skipped, struct b is still there
+        assert!(import_c(header, "a", false).is_err());
+        assert_eq!(import_c(header, "", false).unwrap()[2], "y:u8");
+        assert!(import_c("struct c { uint8_t x : 9; };", "", false).is_err());
+        assert!(import_c("struct c { uint8_t x : -1; };", "", false).is_err());
+        assert!(import_c("struct c { char a[-1]; };", "", false).is_err());
+        assert!(import_c("struct c { char a[0]; };", "", false).is_err());
+        let header = "struct __attribute__((aligned(0))) c { char a; };";
+        assert!(import_c(header, "", false).is_err());
+        let header = "struct c { char a[1 << 62][8]; };";
+        assert!(import_c(header, "", false).is_err());
+        let header = "struct c { unsigned int a[100000000]; };";
+        assert!(import_c(header, "", false).is_err());
+        let header = "struct e { int a[1000]; };\nstruct c { struct e b[2000]; };";
+        assert!(import_c(header, "c", false).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+use crate::mask::parse_number;
+use anyhow::{bail, Context, Result};
+
+// Import of CAN messages from DBC files. Every signal becomes an iarb / uarb
+// field at its absolute bit position: Intel signals are read with the bit
+// order le, Motorola signals with be (see parse_byte_order()). Factor,
+// offset, unit and value tables become the attributes of the field.
+
+#[derive(Debug, Default)]
+struct Signal {
+    name: String,
+    start: usize,
+    len: usize,
+    intel: bool,
+    signed: bool,
+    factor: f64,
+    offset: f64,
+    unit: String,
+    multiplexer: Option<String>, // M for the multiplexer, m3 for signals of value 3
+    names: Vec<(i128, String)>,
+    comment: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    id: u32,
+    name: String,
+    dlc: usize,
+    signals: Vec<Signal>,
+    comment: Option<String>,
+}
+
+// extended CAN ids have bit 31 set in a DBC file
+const EXTENDED_ID_FLAG: u32 = 0x8000_0000;
+
+// the parts of a line, strings in "" are one part (without the quotes)
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            words.push(quoted[..end].to_owned());
+            rest = quoted.get(end + 1..).unwrap_or_default();
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '"')
+                .unwrap_or(rest.len());
+            words.push(rest[..end].to_owned());
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    words
+}
+
+// names and units end up in config lines, they can not contain the separators
+fn config_word(s: &str) -> String {
+    s.trim()
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || ":#,=()".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+// SG_ EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] "rpm" Vector__XXX
+// BO_ <id> <name>: <dlc> <sender>, there may be whitespace before the :
+fn parse_message(line: &str) -> Result<Message> {
+    let (head, tail) = line.split_once(':').context("Missing : in BO_ line.")?;
+    let head: Vec<&str> = head.split_whitespace().collect();
+    let ["BO_", id, name] = head.as_slice() else {
+        bail!("Can not read the name of the message in \"{}\".", line);
+    };
+    let dlc = tail
+        .split_whitespace()
+        .next()
+        .context("Missing size in BO_ line.")?;
+    Ok(Message {
+        id: id.parse()?,
+        name: name.to_string(),
+        dlc: dlc.parse()?,
+        ..Default::default()
+    })
+}
+
+fn parse_signal(line: &str) -> Result<Signal> {
+    let (head, tail) = line.split_once(':').context("Missing : in SG_ line.")?;
+    let head: Vec<&str> = head.split_whitespace().collect();
+    let (name, multiplexer) = match head.as_slice() {
+        ["SG_", name] => (name, None),
+        ["SG_", name, mux] => (name, Some(mux.to_string())),
+        _ => bail!("Can not read the name of the signal in \"{}\".", line),
+    };
+    let tail = tail.trim();
+    let (layout, tail) = tail.split_once(char::is_whitespace).unwrap_or((tail, ""));
+    let (start, layout) = layout.split_once('|').context("Missing | in SG_ line.")?;
+    let (len, layout) = layout.split_once('@').context("Missing @ in SG_ line.")?;
+    let (factor, offset) = tail
+        .split_once('(')
+        .and_then(|(_, t)| t.split_once(')'))
+        .and_then(|(scaling, _)| scaling.split_once(','))
+        .context("Missing (factor,offset) in SG_ line.")?;
+    let unit = tail.split('"').nth(1).unwrap_or_default();
+    Ok(Signal {
+        name: name.to_string(),
+        start: start.trim().parse().context("Invalid start bit.")?,
+        len: len.trim().parse().context("Invalid length.")?,
+        intel: layout.starts_with('1'),
+        signed: layout.ends_with('-'),
+        factor: factor.trim().parse().context("Invalid factor.")?,
+        offset: offset.trim().parse().context("Invalid offset.")?,
+        unit: unit.to_owned(),
+        multiplexer,
+        ..Default::default()
+    })
+}
+
+fn find_signal<'a>(messages: &'a mut [Message], id: &str, name: &str) -> Option<&'a mut Signal> {
+    let id: u32 = id.parse().ok()?;
+    messages
+        .iter_mut()
+        .find(|m| m.id == id)?
+        .signals
+        .iter_mut()
+        .find(|s| s.name == name)
+}
+
+fn parse_dbc(text: &str) -> Result<Vec<Message>> {
+    let mut messages: Vec<Message> = Vec::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((n, line)) = lines.next() {
+        let mut line = line.trim().to_owned();
+        let context = || format!("DBC file, line {}", n + 1);
+        if line.starts_with("BO_ ") {
+            messages.push(parse_message(&line).with_context(context)?);
+        } else if line.starts_with("SG_ ") {
+            let signal = parse_signal(&line).with_context(context)?;
+            messages
+                .last_mut()
+                .with_context(|| format!("Signal without a message in {}.", context()))?
+                .signals
+                .push(signal);
+        } else if line.starts_with("VAL_ ") || line.starts_with("CM_ ") {
+            // these can reach over several lines, they end with a ; outside of ""
+            while line.matches('"').count() % 2 == 1 || !line.trim_end().ends_with(';') {
+                match lines.next() {
+                    Some((_, next)) => line = format!("{}\n{}", line, next),
+                    None => break,
+                }
+            }
+            let words = split_words(line.trim_end().trim_end_matches(';'));
+            match words
+                .iter()
+                .map(|w| w.as_str())
+                .collect::<Vec<&str>>()
+                .as_slice()
+            {
+                ["VAL_", id, name, values @ ..] => {
+                    if let Some(signal) = find_signal(&mut messages, id, name) {
+                        for pair in values.chunks(2) {
+                            if let [value, text] = pair {
+                                let value = value.parse().with_context(context)?;
+                                signal.names.push((value, config_word(text)));
+                            }
+                        }
+                    }
+                }
+                ["CM_", "SG_", id, name, comment] => {
+                    if let Some(signal) = find_signal(&mut messages, id, name) {
+                        signal.comment = Some(comment.to_string());
+                    }
+                }
+                ["CM_", "BO_", id, comment] => {
+                    if let Some(message) = messages.iter_mut().find(|m| id.parse() == Ok(m.id)) {
+                        message.comment = Some(comment.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(messages)
+}
+
+// the --message can be the name of the message or its CAN id
+fn find_message<'a>(messages: &'a [Message], select: &str) -> Result<&'a Message> {
+    let names = || {
+        messages
+            .iter()
+            .map(|m| format!("{} ({:#X})", m.name, m.id & !EXTENDED_ID_FLAG))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+    if select.is_empty() {
+        return match messages {
+            [message] => Ok(message),
+            [] => bail!("The DBC file does not define a message."),
+            _ => bail!(
+                "The DBC file defines several messages ({}), choose one with --message.",
+                names()
+            ),
+        };
+    }
+    let id = parse_number(select).map(|id| id as u32);
+    messages
+        .iter()
+        .find(|m| m.name == select || Some(m.id) == id || Some(m.id & !EXTENDED_ID_FLAG) == id)
+        .with_context(|| format!("The DBC file does not define message {}.", select))
+}
+
+fn signal_line(signal: &Signal) -> String {
+    let mut line = format!(
+        "{}:{}:{}:{}",
+        config_word(&signal.name),
+        if signal.signed { "iarb" } else { "uarb" },
+        signal.len,
+        if signal.intel { "le" } else { "be" }
+    );
+    if signal.factor != 1.0 {
+        line = format!("{}:scale={}", line, signal.factor);
+    }
+    if signal.offset != 0.0 {
+        line = format!("{}:offset={}", line, signal.offset);
+    }
+    if !signal.unit.trim().is_empty() {
+        line = format!("{}:unit={}", line, config_word(&signal.unit));
+    }
+    if !signal.names.is_empty() {
+        let names: Vec<String> = signal
+            .names
+            .iter()
+            .map(|(value, name)| format!("{}={}", value, name))
+            .collect();
+        line = format!("{}:enum({})", line, names.join(","));
+    }
+    let mut comment = signal.comment.clone().unwrap_or_default();
+    match signal.multiplexer.as_deref() {
+        Some("M") => comment = format!("multiplexer {}", comment),
+        Some(mux) => {
+            comment = format!("only valid if the multiplexer is {} {}", &mux[1..], comment)
+        }
+        None => {}
+    }
+    if comment.trim().is_empty() {
+        line
+    } else {
+        format!("{} # {}", line, comment.trim().replace('\n', " "))
+    }
+}
+
+// the config lines for a message of a DBC file. If the file has only one
+// message, select can be empty.
+pub fn import_dbc(text: &str, select: &str) -> Result<Vec<String>> {
+    let messages = parse_dbc(text)?;
+    let message = find_message(&messages, select)?;
+    let mut header = format!(
+        "# message {} ({:#X}), {} bytes",
+        message.name,
+        message.id & !EXTENDED_ID_FLAG,
+        message.dlc
+    );
+    if let Some(comment) = &message.comment {
+        header = format!("{}, {}", header, comment.replace('\n', " "));
+    }
+    let mut lines = vec![header];
+    for signal in message.signals.iter() {
+        // Intel signals start at their least significant bit, counted from the
+        // least significant bit of each byte. Motorola signals start at their
+        // most significant bit, which is counted the same way, but mview
+        // counts from the most significant bit of each byte.
+        let start = if signal.intel {
+            signal.start
+        } else {
+            signal.start - signal.start % 8 + (7 - signal.start % 8)
+        };
+        if start + signal.len > message.dlc * 8 {
+            bail!(
+                "Signal {} of message {} does not fit into {} bytes.",
+                signal.name,
+                message.name,
+                message.dlc
+            );
+        }
+        lines.push(format!("@bit {}", start));
+        lines.push(signal_line(signal));
+    }
+    // the chunk is as long as the message
+    lines.push(format!("@byte {}", message.dlc));
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBC: &str = r#"VERSION ""
+
+NS_ :
+	CM_
+	VAL_
+
+BU_: ECU Dash
+
+BO_ 100 EngineData: 8 ECU
+ SG_ EngineSpeed : 0|16@1+ (0.125,0) [0|8031.875] "rpm" Dash
+ SG_ Temp : 23|8@0- (1,-40) [-40|215] "deg C" Dash
+ SG_ Gear : 27|3@1+ (1,0) [0|7] "" Dash
+
+BO_ 2566844926 Extended : 2 ECU
+ SG_ Mux M : 0|2@1+ (1,0) [0|3] "" Dash
+ SG_ Value m1 : 8|8@1+ (1,0) [0|255] "" Dash
+
+CM_ SG_ 100 EngineSpeed "Speed of the
+engine";
+VAL_ 100 Gear 0 "Neutral" 1 "First gear" ;
+"#;
+
+    #[test]
+    fn test_import_dbc() {
+        assert_eq!(
+            import_dbc(DBC, "EngineData").unwrap()[1..],
+            [
+                "@bit 0",
+                "EngineSpeed:uarb:16:le:scale=0.125:unit=rpm # Speed of the engine",
+                "@bit 16",
+                "Temp:iarb:8:be:offset=-40:unit=deg_C",
+                "@bit 27",
+                "Gear:uarb:3:le:enum(0=Neutral,1=First_gear)",
+                "@byte 8",
+            ]
+        );
+        // by id, with and without the extended flag
+        assert!(import_dbc(DBC, "100").is_ok());
+        assert!(import_dbc(DBC, "0x64").is_ok());
+        let extended = import_dbc(DBC, "0x18FEF1FE").unwrap();
+        assert_eq!(extended[2], "Mux:uarb:2:le # multiplexer");
+        assert_eq!(
+            extended[4],
+            "Value:uarb:8:le # only valid if the multiplexer is 1"
+        );
+    }
+    #[test]
+    fn test_import_dbc_errors() {
+        assert!(import_dbc(DBC, "").is_err()); // several messages
+        assert!(import_dbc(DBC, "Missing").is_err());
+        let too_long = "BO_ 1 Short: 1 ECU\n SG_ A : 0|16@1+ (1,0) [0|0] \"\" ECU";
+        assert!(import_dbc(too_long, "").is_err());
+        assert!(import_dbc("BO_ 1 : 1 ECU", "").is_err()); // no name
+    }
+}
@@ -0,0 +1,778 @@
+use crate::{
+    args::Args,
+    chunksize_by_config,
+    expr::{Expr, Value},
+    field_size_in_bits,
+    mask::{align_bitpos, apply_directive, item_alignment, parse_mask, MaskItem},
+    parse_byte_order, parse_config_line, parse_field_attrs, read_config, wireshark, FieldAttrs,
+    Format, BYTE_TO_BIT,
+};
+use anyhow::{bail, Context, Result};
+use std::{collections::HashSet, fs};
+
+// more fields make code nobody can read and a dissector Wireshark chokes on
+const MAX_PLACED_FIELDS: usize = 100_000;
+
+// Export of a mask as code. The fields are placed like mview reads them, so
+// the C struct has the same layout as the chunk and the Rust code decodes a
+// chunk to the same values as mview.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lang {
+    C,
+    Rust,
+    Lua, // a Wireshark dissector
+}
+
+impl Lang {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "c" => Some(Self::C),
+            "rust" | "rs" => Some(Self::Rust),
+            "lua" => Some(Self::Lua),
+            _ => None,
+        }
+    }
+}
+
+// a field of the mask at its position in the chunk
+pub(crate) struct Placed {
+    pub name: String,
+    pub val_type: String,
+    pub format: Format,
+    pub len: usize, // the length of the config line, bytes of strings, bits of bit fields
+    pub bitpos: usize,
+    pub size: usize, // in bits
+    pub byte_order: Option<bool>,
+    pub attrs: FieldAttrs,
+    pub notes: Vec<String>, // unit, scale, offset, enum and description
+}
+
+pub(crate) enum Node {
+    Field(Placed),
+    Overlay(usize, usize, Vec<Vec<Node>>), // start, end and the members
+    Computed(String),
+}
+
+pub(crate) fn place(
+    items: &[MaskItem],
+    bitpos: &mut usize,
+    repr_c: &mut bool,
+    suffix: &str,
+    nodes: &mut Vec<Node>,
+) -> Result<()> {
+    for item in items.iter() {
+        if *repr_c {
+            *bitpos = align_bitpos(*bitpos, 0, item_alignment(item)?);
+        }
+        match item {
+            MaskItem::Field(conf_line) => {
+                let (name, val_type, format, len) = parse_config_line(conf_line)?;
+                let size = field_size_in_bits(val_type, len)
+                    .with_context(|| format!("Unknown type {} of field {}.", val_type, name))?;
+                let attrs = parse_field_attrs(conf_line)?;
+                let mut notes = Vec::new();
+                if let Some(unit) = &attrs.unit {
+                    notes.push(format!("unit {}", unit));
+                }
+                if let Some(scale) = attrs.scale {
+                    notes.push(format!("scale {}", scale));
+                }
+                if let Some(offset) = attrs.offset {
+                    notes.push(format!("offset {}", offset));
+                }
+                if !attrs.names.is_empty() {
+                    let names: Vec<String> = attrs
+                        .names
+                        .iter()
+                        .map(|(value, name)| format!("{}={}", value, name))
+                        .collect();
+                    notes.push(names.join(" "));
+                }
+                if let Some((_, description)) = conf_line.split_once('#') {
+                    if !description.trim().is_empty() {
+                        notes.push(description.trim().to_owned());
+                    }
+                }
+                nodes.push(Node::Field(Placed {
+                    name: format!("{}{}", name, suffix),
+                    val_type: val_type.to_lowercase(),
+                    format,
+                    len,
+                    bitpos: *bitpos,
+                    size,
+                    byte_order: parse_byte_order(conf_line)?,
+                    attrs,
+                    notes,
+                }));
+                *bitpos += size;
+            }
+            MaskItem::Directive(directive) => apply_directive(directive, bitpos, 0, repr_c),
+            MaskItem::Struct(members) => place(members, bitpos, repr_c, suffix, nodes)?,
+            MaskItem::Overlay(members) => {
+                let start = *bitpos;
+                let mut end = start;
+                let mut alternatives = Vec::new();
+                for member in members.iter() {
+                    *bitpos = start;
+                    let mut alternative = Vec::new();
+                    place(
+                        std::slice::from_ref(member),
+                        bitpos,
+                        repr_c,
+                        suffix,
+                        &mut alternative,
+                    )?;
+                    end = end.max(*bitpos);
+                    alternatives.push(alternative);
+                }
+                *bitpos = end;
+                nodes.push(Node::Overlay(start, end, alternatives));
+            }
+            MaskItem::Repeat(Some(Expr::Num(Value::Int(count))), body) => {
+                for i in 0..*count {
+                    if nodes.len() > MAX_PLACED_FIELDS {
+                        bail!(
+                            "The repeat blocks make more than {} fields, too many to place.",
+                            MAX_PLACED_FIELDS
+                        );
+                    }
+                    place(body, bitpos, repr_c, &format!("{}_{}", suffix, i), nodes)?;
+                }
+            }
+            MaskItem::Repeat(..) => {
+                bail!("A repeat block without a fixed count has no fixed layout, its size depends on the data.")
+            }
+            MaskItem::If(..) => {
+                bail!("An if block has no fixed layout, its size depends on the data.")
+            }
+            MaskItem::Tlv(conf_line, _) => bail!(
+                "Tlv field {} has no fixed layout, its size depends on the data.",
+                parse_config_line(conf_line)?.0
+            ),
+            MaskItem::Computed(name, expr, _) => {
+                nodes.push(Node::Computed(format!("{} = {}", name, expr)))
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn is_bit_field(val_type: &str) -> bool {
+    matches!(val_type, "bool1" | "uarb" | "iarb" | "bitgap")
+}
+
+// the smallest of 8, 16, 32, 64 and 128 bits that holds a bit field
+pub(crate) fn storage_bits(size: usize) -> Result<usize> {
+    [8, 16, 32, 64, 128]
+        .into_iter()
+        .find(|bits| size <= *bits)
+        .with_context(|| format!("Bit fields can be at most 128 bits long, not {}.", size))
+}
+
+pub(crate) fn c_ident(name: &str) -> String {
+    let ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if ident.starts_with(|c: char| c.is_ascii_digit()) || ident.is_empty() {
+        format!("_{}", ident)
+    } else {
+        ident
+    }
+}
+
+const RUST_KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "yield",
+];
+
+// EngineSpeed and engine.speed become engine_speed
+fn rust_ident(name: &str) -> String {
+    let mut ident = String::new();
+    let mut previous = '_';
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && (previous.is_ascii_lowercase() || previous.is_ascii_digit()) {
+            ident.push('_');
+        }
+        ident.push(if c.is_ascii_alphanumeric() {
+            c.to_ascii_lowercase()
+        } else {
+            '_'
+        });
+        previous = c;
+    }
+    let ident = c_ident(&ident);
+    if RUST_KEYWORDS.contains(&ident.as_str()) {
+        format!("{}_", ident)
+    } else {
+        ident
+    }
+}
+
+// status_msg becomes StatusMsg
+fn rust_type_name(name: &str) -> String {
+    let name: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        format!("Mask{}", name)
+    } else {
+        name
+    }
+}
+
+pub(crate) fn check_unique(names: &mut HashSet<String>, ident: &str, name: &str) -> Result<()> {
+    if !names.insert(ident.to_owned()) {
+        bail!(
+            "Field {} has the same name as another field in the exported code ({}).",
+            name,
+            ident
+        );
+    }
+    Ok(())
+}
+
+struct CWriter {
+    little_endian: bool,
+    n_padding: usize,
+    names: HashSet<String>,
+}
+
+impl CWriter {
+    fn padding(&mut self, from: usize, to: usize, indent: &str, out: &mut Vec<String>) {
+        let mut from = from;
+        let bitfield = |bits: usize, n: &mut usize, out: &mut Vec<String>| {
+            *n += 1;
+            out.push(format!("{}uint8_t padding_{} : {};", indent, n, bits));
+        };
+        if !from.is_multiple_of(BYTE_TO_BIT) && from < to {
+            let bits = (from.next_multiple_of(BYTE_TO_BIT)).min(to) - from;
+            bitfield(bits, &mut self.n_padding, out);
+            from += bits;
+        }
+        let bytes = (to - from.min(to)) / BYTE_TO_BIT;
+        if bytes > 0 {
+            self.n_padding += 1;
+            out.push(format!(
+                "{}uint8_t padding_{}[{}];",
+                indent, self.n_padding, bytes
+            ));
+            from += bytes * BYTE_TO_BIT;
+        }
+        if from < to {
+            bitfield(to - from, &mut self.n_padding, out);
+        }
+    }
+
+    fn field(&mut self, field: &Placed, indent: &str, out: &mut Vec<String>) -> Result<()> {
+        let ident = c_ident(&field.name);
+        check_unique(&mut self.names, &ident, &field.name)?;
+        let mut notes = Vec::new();
+        let member = match field.val_type.as_str() {
+            "bool1" | "uarb" | "iarb" | "bitgap" => {
+                let bits = storage_bits(field.size)?;
+                if bits > 64 {
+                    bail!("Field {} is longer than a C bitfield can be.", field.name);
+                }
+                if field.size > 1 && field.byte_order.is_none() {
+                    notes.push("first bit is the least significant one, not a C bitfield layout, add le or be".to_owned());
+                }
+                let sign = if field.val_type == "iarb" { "" } else { "u" };
+                format!("{}int{}_t {} : {}", sign, bits, ident, field.size)
+            }
+            _ if !field.bitpos.is_multiple_of(BYTE_TO_BIT) => bail!(
+                "Field {} does not start at a byte, in C only bit fields can.",
+                field.name
+            ),
+            "bool8" => format!("bool {}", ident),
+            "string" => format!("char {}[{}]", ident, field.len),
+            "bytegap" => format!("uint8_t {}[{}]", ident, field.len),
+            "u128" => format!("unsigned __int128 {}", ident),
+            "i128" => format!("__int128 {}", ident),
+            "f32" => format!("float {}", ident),
+            "f64" => format!("double {}", ident),
+            val_type => {
+                let sign = if val_type.starts_with('u') { "u" } else { "" };
+                format!("{}int{}_t {}", sign, field.size, ident)
+            }
+        };
+        if field.size > BYTE_TO_BIT && !is_bit_field(&field.val_type) {
+            // floats are read as big endian unless the field says le
+            let little_endian = match field.val_type.as_str() {
+                "f32" | "f64" => field.byte_order == Some(true),
+                "string" | "bytegap" => false,
+                _ => field.byte_order.unwrap_or(self.little_endian),
+            };
+            if !matches!(field.val_type.as_str(), "string" | "bytegap") {
+                notes.push(
+                    if little_endian {
+                        "little endian"
+                    } else {
+                        "big endian"
+                    }
+                    .to_owned(),
+                );
+            }
+        }
+        notes.extend(field.notes.iter().cloned());
+        if notes.is_empty() {
+            out.push(format!("{}{};", indent, member));
+        } else {
+            out.push(format!("{}{}; /* {} */", indent, member, notes.join(", ")));
+        }
+        Ok(())
+    }
+
+    fn members(
+        &mut self,
+        nodes: &[Node],
+        pos: &mut usize,
+        indent: &str,
+        out: &mut Vec<String>,
+    ) -> Result<()> {
+        for node in nodes.iter() {
+            match node {
+                Node::Field(field) => {
+                    if field.bitpos < *pos {
+                        bail!(
+                            "Field {} overlaps the field before it, in C only members of an overlay can.",
+                            field.name
+                        );
+                    }
+                    self.padding(*pos, field.bitpos, indent, out);
+                    self.field(field, indent, out)?;
+                    *pos = field.bitpos + field.size;
+                }
+                Node::Overlay(start, end, alternatives) => {
+                    if *start < *pos {
+                        bail!("An overlay overlaps the field before it.");
+                    }
+                    self.padding(*pos, *start, indent, out);
+                    out.push(format!("{}union {{", indent));
+                    let inner = format!("{}    ", indent);
+                    for alternative in alternatives.iter() {
+                        let mut alt_pos = *start;
+                        match alternative.as_slice() {
+                            [Node::Field(field)] if field.bitpos == *start => {
+                                self.field(field, &inner, out)?
+                            }
+                            _ => {
+                                out.push(format!("{}struct {{", inner));
+                                self.members(
+                                    alternative,
+                                    &mut alt_pos,
+                                    &format!("{}    ", inner),
+                                    out,
+                                )?;
+                                out.push(format!("{}}};", inner));
+                            }
+                        }
+                    }
+                    out.push(format!("{}}};", indent));
+                    *pos = *end;
+                }
+                Node::Computed(computed) => {
+                    out.push(format!("{}/* computed by mview: {} */", indent, computed))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// a packed C struct with the layout of the chunk
+pub fn export_c(config_lines: &[String], name: &str, little_endian: bool) -> Result<String> {
+    let mut nodes = Vec::new();
+    place(
+        &parse_mask(config_lines)?,
+        &mut 0,
+        &mut false,
+        "",
+        &mut nodes,
+    )?;
+    let size = chunksize_by_config(config_lines)?.div_ceil(BYTE_TO_BIT);
+    let name = c_ident(name);
+    let mut writer = CWriter {
+        little_endian,
+        n_padding: 0,
+        names: HashSet::new(),
+    };
+    let mut out = vec![
+        "#include <stdbool.h>".to_owned(),
+        "#include <stdint.h>".to_owned(),
+        String::new(),
+        format!(
+            "/* {} bytes. Multi-byte fields are stored in the byte order noted",
+            size
+        ),
+        " * behind them. Bit fields read with le are laid out like GCC does it on a".to_owned(),
+        " * little endian machine, the ones read with be like on a big endian one. */".to_owned(),
+        format!("struct __attribute__((packed)) {} {{", name),
+    ];
+    let mut pos = 0;
+    writer.members(&nodes, &mut pos, "    ", &mut out)?;
+    writer.padding(pos, size * BYTE_TO_BIT, "    ", &mut out);
+    out.push("};".to_owned());
+    out.push(format!(
+        "_Static_assert(sizeof(struct {}) == {}, \"size of struct {}\");",
+        name, size, name
+    ));
+    Ok(out.join("\n") + "\n")
+}
+
+#[derive(Default)]
+struct RustWriter {
+    little_endian: bool,
+    names: HashSet<String>,
+    members: Vec<String>,
+    decode: Vec<String>,
+    uses_take: bool,
+    uses_signed: bool,
+    orders: Vec<&'static str>, // the bit orders that are used
+}
+
+impl RustWriter {
+    fn order(&mut self, byte_order: Option<bool>) -> &'static str {
+        let order = match byte_order {
+            Some(true) => "Le",
+            Some(false) => "Be",
+            None => "Mview",
+        };
+        if !self.orders.contains(&order) {
+            self.orders.push(order);
+        }
+        order
+    }
+
+    fn field(&mut self, field: &Placed) -> Result<()> {
+        let pos = field.bitpos;
+        let (rust_type, decode) = match field.val_type.as_str() {
+            // gaps are not part of the struct
+            "bytegap" | "bitgap" => return Ok(()),
+            "bool1" => (
+                "bool".to_owned(),
+                format!(
+                    "bits(data, {}, 1, BitOrder::{}) != 0",
+                    pos,
+                    self.order(field.byte_order)
+                ),
+            ),
+            "uarb" | "iarb" => {
+                let bits = storage_bits(field.size)?;
+                let (sign, function) = match field.val_type.as_str() {
+                    "iarb" => {
+                        self.uses_signed = true;
+                        ("i", "signed_bits")
+                    }
+                    _ => ("u", "bits"),
+                };
+                let rust_type = format!("{}{}", sign, bits);
+                let order = self.order(field.byte_order);
+                let decode = format!(
+                    "{}(data, {}, {}, BitOrder::{})",
+                    function, pos, field.size, order
+                );
+                if bits == 128 {
+                    (rust_type, decode)
+                } else {
+                    (rust_type.clone(), format!("{} as {}", decode, rust_type))
+                }
+            }
+            "bool8" => {
+                self.uses_take = true;
+                (
+                    "bool".to_owned(),
+                    format!("take::<1>(data, {})[0] != 0", pos),
+                )
+            }
+            "string" => {
+                self.uses_take = true;
+                (
+                    format!("[u8; {}]", field.len),
+                    format!("take(data, {})", pos),
+                )
+            }
+            val_type => {
+                self.uses_take = true;
+                let little_endian = match val_type {
+                    "f32" | "f64" => field.byte_order == Some(true),
+                    _ => field.byte_order.unwrap_or(self.little_endian),
+                };
+                let function = if little_endian {
+                    "from_le_bytes"
+                } else {
+                    "from_be_bytes"
+                };
+                (
+                    val_type.to_owned(),
+                    format!("{}::{}(take(data, {}))", val_type, function, pos),
+                )
+            }
+        };
+        let ident = rust_ident(&field.name);
+        check_unique(&mut self.names, &ident, &field.name)?;
+        if !field.notes.is_empty() {
+            self.members
+                .push(format!("    /// {}", field.notes.join(", ")));
+        }
+        self.members
+            .push(format!("    pub {}: {},", ident, rust_type));
+        self.decode
+            .push(format!("            {}: {},", ident, decode));
+        Ok(())
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> Result<()> {
+        for node in nodes.iter() {
+            match node {
+                Node::Field(field) => self.field(field)?,
+                // every member of an overlay is decoded from the same position
+                Node::Overlay(_, _, alternatives) => {
+                    for alternative in alternatives.iter() {
+                        self.nodes(alternative)?;
+                    }
+                }
+                Node::Computed(computed) => self
+                    .members
+                    .push(format!("    // computed by mview: {}", computed)),
+            }
+        }
+        Ok(())
+    }
+}
+
+// a Rust struct with a decode function that reads a chunk like mview
+pub fn export_rust(config_lines: &[String], name: &str, little_endian: bool) -> Result<String> {
+    let mut nodes = Vec::new();
+    place(
+        &parse_mask(config_lines)?,
+        &mut 0,
+        &mut false,
+        "",
+        &mut nodes,
+    )?;
+    let size = chunksize_by_config(config_lines)?.div_ceil(BYTE_TO_BIT);
+    let name = rust_type_name(name);
+    let mut writer = RustWriter {
+        little_endian,
+        ..Default::default()
+    };
+    writer.nodes(&nodes)?;
+    let mut out = vec![format!("/// {} bytes", size)];
+    out.push("#[derive(Debug, Clone, PartialEq)]".to_owned());
+    out.push(format!("pub struct {} {{", name));
+    out.extend(writer.members);
+    out.push("}".to_owned());
+    out.push(String::new());
+    out.push(format!("impl {} {{", name));
+    out.push(format!("    pub const SIZE: usize = {};", size));
+    out.push(String::new());
+    out.push("    /// None if data is shorter than SIZE".to_owned());
+    out.push("    pub fn decode(data: &[u8]) -> Option<Self> {".to_owned());
+    out.push("        if data.len() < Self::SIZE {".to_owned());
+    out.push("            return None;".to_owned());
+    out.push("        }".to_owned());
+    out.push("        Some(Self {".to_owned());
+    out.extend(writer.decode);
+    out.push("        })".to_owned());
+    out.push("    }".to_owned());
+    out.push("}".to_owned());
+    if writer.uses_take {
+        out.push(
+            "
+// N bytes from a bit position, bits are counted from the most significant
+// bit of the first byte
+fn take<const N: usize>(data: &[u8], bitpos: usize) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let pos = bitpos + i * 8;
+        *byte = match pos % 8 {
+            0 => data[pos / 8],
+            shift => (data[pos / 8] << shift) | (data[pos / 8 + 1] >> (8 - shift)),
+        };
+    }
+    bytes
+}"
+            .to_owned(),
+        );
+    }
+    if !writer.orders.is_empty() {
+        let variants: Vec<String> = writer
+            .orders
+            .iter()
+            .map(|order| format!("    {},", order))
+            .collect();
+        let arms: Vec<String> = writer
+            .orders
+            .iter()
+            .map(|order| {
+                let index = match *order {
+                    "Le" => "(bitpos + i) / 8 * 8 + 7 - (bitpos + i) % 8",
+                    "Be" => "bitpos + len - 1 - i",
+                    _ => "bitpos + i",
+                };
+                format!("            BitOrder::{} => {},", order, index)
+            })
+            .collect();
+        out.push(format!(
+            "
+// Le: the first bit is the least significant one, bits are counted from the
+// least significant bit of each byte. Be: the first bit is the most
+// significant one. Mview: the first bit is the least significant one.
+#[derive(Clone, Copy)]
+enum BitOrder {{
+{}
+}}
+
+// a bit field like mview reads it, bits are counted from the most
+// significant bit of the first byte
+fn bits(data: &[u8], bitpos: usize, len: usize, order: BitOrder) -> u128 {{
+    let mut value = 0;
+    for i in 0..len {{
+        let index = match order {{
+{}
+        }};
+        let bit = (data[index / 8] >> (7 - index % 8)) & 1;
+        value |= (bit as u128) << i;
+    }}
+    value
+}}",
+            variants.join("\n"),
+            arms.join("\n")
+        ));
+        if writer.uses_signed {
+            out.push(
+                "
+// a bit field in two's complement
+fn signed_bits(data: &[u8], bitpos: usize, len: usize, order: BitOrder) -> i128 {
+    let shift = 128 - len;
+    ((bits(data, bitpos, len, order) << shift) as i128) >> shift
+}"
+                .to_owned(),
+            );
+        }
+    }
+    Ok(out.join("\n") + "\n")
+}
+
+// export the mask of a config, without outfile the code is printed
+pub fn export(
+    config_path: &str,
+    outfile: &str,
+    lang: Lang,
+    udp_port: Option<u16>,
+    args: &Args,
+) -> Result<()> {
+    let config_lines = read_config(config_path, &[], args)
+        .with_context(|| format!("Could not read config {}", config_path))?;
+    // the struct is called like the config, or the struct or message chosen in it
+    let name = if args.select.is_empty() {
+        let file_name = config_path.rsplit('/').next().unwrap_or(config_path);
+        file_name.split('.').next().unwrap_or(file_name).to_owned()
+    } else {
+        args.select.clone()
+    };
+    let code = match lang {
+        Lang::C => export_c(&config_lines, &name, args.little_endian)?,
+        Lang::Rust => export_rust(&config_lines, &name, args.little_endian)?,
+        Lang::Lua => wireshark::export_lua(&config_lines, &name, args.little_endian, udp_port)?,
+    };
+    let code = match lang {
+        Lang::C => format!(
+            "/* generated by mview export from {} */\n{}",
+            config_path, code
+        ),
+        Lang::Rust => format!(
+            "// generated by mview export from {}\n{}",
+            config_path, code
+        ),
+        Lang::Lua => format!(
+            "-- generated by mview export from {}\n{}",
+            config_path, code
+        ),
+    };
+    if outfile.is_empty() {
+        print!("{}", code);
+        return Ok(());
+    }
+    fs::write(outfile, code).with_context(|| format!("Could not write to {}", outfile))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(config: &str) -> Vec<String> {
+        config.lines().map(|s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_export_c() {
+        let config = lines(
+            "Id:u16:hex # the id
+Flags:uarb:3:le
+@bit 24
+Volt:f32:unit=V
+Name:string:4
+@byte 12",
+        );
+        assert_eq!(
+            export_c(&config, "status msg", true)
+                .unwrap()
+                .lines()
+                .skip(6)
+                .collect::<Vec<&str>>(),
+            [
+                "struct __attribute__((packed)) status_msg {",
+                "    uint16_t Id; /* little endian, the id */",
+                "    uint8_t Flags : 3;",
+                "    uint8_t padding_1 : 5;",
+                "    float Volt; /* big endian, unit V */",
+                "    char Name[4];",
+                "    uint8_t padding_2[1];",
+                "};",
+                "_Static_assert(sizeof(struct status_msg) == 12, \"size of struct status_msg\");",
+            ]
+        );
+    }
+    #[test]
+    fn test_export_rust() {
+        let config = lines(
+            "EngineSpeed:u16:le
+Temp:iarb:4:be
+Mode:bool1",
+        );
+        let code = export_rust(&config, "engine_data", false).unwrap();
+        assert!(code.contains("pub struct EngineData {"));
+        assert!(code.contains("    pub const SIZE: usize = 3;"));
+        assert!(code.contains("            engine_speed: u16::from_le_bytes(take(data, 0)),"));
+        assert!(code.contains("            temp: signed_bits(data, 16, 4, BitOrder::Be) as i8,"));
+        assert!(code.contains("            mode: bits(data, 20, 1, BitOrder::Mview) != 0,"));
+    }
+    #[test]
+    fn test_export_errors() {
+        // the size depends on the data
+        assert!(export_c(&lines("repeat {\nA:u8\n} until end"), "x", false).is_err());
+        assert!(export_rust(&lines("A:u8\nif A {\nB:u8\n}"), "x", false).is_err());
+        // C members can not overlap or start inside a byte
+        assert!(export_c(&lines("A:u16\n@byte 1\nB:u8"), "x", false).is_err());
+        assert!(export_c(&lines("A:bool1\nB:u16"), "x", false).is_err());
+        assert!(export_rust(&lines("A:bool1\nB:u16"), "x", false).is_ok());
+        // names that are the same in code
+        assert!(export_rust(&lines("a.b:u8\na_b:u8"), "x", false).is_err());
+        // too many fields
+        assert!(export_c(&lines("repeat {\nA:u8\n} times 200000"), "x", false).is_err());
+    }
+}
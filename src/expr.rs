@@ -0,0 +1,438 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+
+// value of a decoded field, as far as it is a number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+}
+
+impl Value {
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Value::Int(i) => i as f64,
+            Value::Float(f) => f,
+        }
+    }
+    pub fn is_true(&self) -> bool {
+        match *self {
+            Value::Int(i) => i != 0,
+            Value::Float(f) => f != 0.0,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+// the values of the fields decoded so far in a chunk, by field name
+pub type Scope = HashMap<String, Value>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    BitNot,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinOp {
+    // binding strength, higher binds tighter. Same order as in C.
+    fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 10,
+            BinOp::Add | BinOp::Sub => 9,
+            BinOp::Shl | BinOp::Shr => 8,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 7,
+            BinOp::Eq | BinOp::Ne => 6,
+            BinOp::BitAnd => 5,
+            BinOp::BitXor => 4,
+            BinOp::BitOr => 3,
+            BinOp::And => 2,
+            BinOp::Or => 1,
+        }
+    }
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::BitAnd => "&",
+            BinOp::BitXor => "^",
+            BinOp::BitOr => "|",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(Value),
+    Ident(String),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // brackets are only needed around binary operations that bind weaker than their parent
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        match self {
+            Expr::Binary(op, ..) if op.precedence() < min_precedence => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+// writes the expression back in the syntax parse_expr() reads
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Num(Value::Int(i)) => write!(f, "{}", i),
+            Expr::Num(Value::Float(x)) => write!(f, "{:?}", x), // keeps the . of 1.0
+            Expr::Ident(name) => write!(f, "{}", name),
+            Expr::Unary(op, operand) => {
+                let symbol = match op {
+                    UnOp::Neg => "-",
+                    UnOp::BitNot => "~",
+                    UnOp::Not => "!",
+                };
+                write!(f, "{}", symbol)?;
+                // unary operators bind tighter than all binary ones
+                operand.fmt_operand(f, u8::MAX)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                lhs.fmt_operand(f, op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                // operators are left associative, a - (b - c) needs its brackets
+                rhs.fmt_operand(f, op.precedence() + 1)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Num(Value),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+// longer operators first, so "<<" is not read as two "<"
+const OPERATORS: [&str; 21] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*", "/", "%", "+", "-", "<", ">", "&", "^",
+    "|", "~", "!", "=",
+];
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' {
+            tokens.push(Token::LParen);
+            rest = &rest[1..];
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            rest = &rest[1..];
+        } else if c.is_ascii_digit() {
+            let len = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+            let literal = &rest[..len];
+            let value = if let Some(hex) = literal.strip_prefix("0x") {
+                Value::Int(i128::from_str_radix(hex, 16)?)
+            } else if let Some(bin) = literal.strip_prefix("0b") {
+                Value::Int(i128::from_str_radix(bin, 2)?)
+            } else if literal.contains('.') {
+                Value::Float(literal.parse()?)
+            } else {
+                Value::Int(literal.parse()?)
+            };
+            tokens.push(Token::Num(value));
+            rest = &rest[len..];
+        } else if is_ident_char(c) {
+            let len = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_owned()));
+            rest = &rest[len..];
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .with_context(|| format!("Unexpected character '{}' in expression.", c))?;
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn binary_op(op: &str) -> Option<BinOp> {
+    match op {
+        "*" => Some(BinOp::Mul),
+        "/" => Some(BinOp::Div),
+        "%" => Some(BinOp::Rem),
+        "+" => Some(BinOp::Add),
+        "-" => Some(BinOp::Sub),
+        "<<" => Some(BinOp::Shl),
+        ">>" => Some(BinOp::Shr),
+        "<" => Some(BinOp::Lt),
+        "<=" => Some(BinOp::Le),
+        ">" => Some(BinOp::Gt),
+        ">=" => Some(BinOp::Ge),
+        "==" => Some(BinOp::Eq),
+        "!=" => Some(BinOp::Ne),
+        "&" => Some(BinOp::BitAnd),
+        "^" => Some(BinOp::BitXor),
+        "|" => Some(BinOp::BitOr),
+        "&&" => Some(BinOp::And),
+        "||" => Some(BinOp::Or),
+        _ => None,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+    // precedence climbing, only operators that bind tighter than min_precedence are taken
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = match binary_op(op) {
+                Some(op) if op.precedence() >= min_precedence => op,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self) -> Result<Expr> {
+        let op = match self.peek() {
+            Some(Token::Op("-")) => Some(UnOp::Neg),
+            Some(Token::Op("~")) => Some(UnOp::BitNot),
+            Some(Token::Op("!")) => Some(UnOp::Not),
+            Some(Token::Op("+")) => {
+                self.pos += 1;
+                return self.unary();
+            }
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            None => self.primary(),
+        }
+    }
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Num(value)) => Ok(Expr::Num(*value)),
+            Some(Token::Ident(name)) => Ok(Expr::Ident(name.clone())),
+            Some(Token::LParen) => {
+                let expr = self.binary(0)?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => bail!("Missing ) in expression."),
+                }
+            }
+            Some(token) => bail!("Unexpected {:?} in expression.", token),
+            None => bail!("Unexpected end of expression."),
+        }
+    }
+}
+
+pub fn parse_expr(input: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let expr = parser.binary(0)?;
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected {:?} in expression.", token);
+    }
+    Ok(expr)
+}
+
+fn int_op(op: BinOp, a: i128, b: i128) -> Result<i128> {
+    let shift = || u32::try_from(b).ok().filter(|b| *b < 128);
+    Ok(match op {
+        BinOp::Mul => a.wrapping_mul(b),
+        BinOp::Div => a.checked_div(b).context("Division by zero.")?,
+        BinOp::Rem => a.checked_rem(b).context("Division by zero.")?,
+        BinOp::Add => a.wrapping_add(b),
+        BinOp::Sub => a.wrapping_sub(b),
+        BinOp::Shl => a << shift().context("Invalid shift.")?,
+        BinOp::Shr => a >> shift().context("Invalid shift.")?,
+        BinOp::BitAnd => a & b,
+        BinOp::BitXor => a ^ b,
+        BinOp::BitOr => a | b,
+        BinOp::Lt => (a < b) as i128,
+        BinOp::Le => (a <= b) as i128,
+        BinOp::Gt => (a > b) as i128,
+        BinOp::Ge => (a >= b) as i128,
+        BinOp::Eq => (a == b) as i128,
+        BinOp::Ne => (a != b) as i128,
+        BinOp::And | BinOp::Or => unreachable!("logical operators are evaluated lazily"),
+    })
+}
+
+fn float_op(op: BinOp, a: f64, b: f64) -> Result<Value> {
+    Ok(match op {
+        BinOp::Mul => Value::Float(a * b),
+        BinOp::Div => Value::Float(a / b),
+        BinOp::Rem => Value::Float(a % b),
+        BinOp::Add => Value::Float(a + b),
+        BinOp::Sub => Value::Float(a - b),
+        BinOp::Lt => Value::Int((a < b) as i128),
+        BinOp::Le => Value::Int((a <= b) as i128),
+        BinOp::Gt => Value::Int((a > b) as i128),
+        BinOp::Ge => Value::Int((a >= b) as i128),
+        BinOp::Eq => Value::Int((a == b) as i128),
+        BinOp::Ne => Value::Int((a != b) as i128),
+        _ => bail!("Bit operations need integers."),
+    })
+}
+
+pub fn eval(expr: &Expr, scope: &Scope) -> Result<Value> {
+    match expr {
+        Expr::Num(value) => Ok(*value),
+        Expr::Ident(name) => scope
+            .get(name)
+            .copied()
+            .with_context(|| format!("No value for field {}.", name)),
+        Expr::Unary(op, operand) => {
+            let value = eval(operand, scope)?;
+            match (op, value) {
+                (UnOp::Neg, Value::Int(i)) => Ok(Value::Int(i.wrapping_neg())),
+                (UnOp::Neg, Value::Float(f)) => Ok(Value::Float(-f)),
+                (UnOp::BitNot, Value::Int(i)) => Ok(Value::Int(!i)),
+                (UnOp::BitNot, Value::Float(_)) => bail!("Bit operations need integers."),
+                (UnOp::Not, value) => Ok(Value::Int(!value.is_true() as i128)),
+            }
+        }
+        Expr::Binary(BinOp::And, lhs, rhs) => Ok(Value::Int(
+            (eval(lhs, scope)?.is_true() && eval(rhs, scope)?.is_true()) as i128,
+        )),
+        Expr::Binary(BinOp::Or, lhs, rhs) => Ok(Value::Int(
+            (eval(lhs, scope)?.is_true() || eval(rhs, scope)?.is_true()) as i128,
+        )),
+        Expr::Binary(op, lhs, rhs) => match (eval(lhs, scope)?, eval(rhs, scope)?) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(int_op(*op, a, b)?)),
+            (a, b) => float_op(*op, a.as_f64(), b.as_f64()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_str(input: &str, scope: &Scope) -> Value {
+        eval(&parse_expr(input).unwrap(), scope).unwrap()
+    }
+
+    #[test]
+    fn test_eval_precedence() {
+        let scope = Scope::new();
+        assert_eq!(eval_str("1 + 2 * 3", &scope), Value::Int(7));
+        assert_eq!(eval_str("(1 + 2) * 3", &scope), Value::Int(9));
+        assert_eq!(eval_str("1 << 4 | 1", &scope), Value::Int(17));
+        assert_eq!(eval_str("10 - 4 - 3", &scope), Value::Int(3));
+        assert_eq!(eval_str("-2 * -3", &scope), Value::Int(6));
+        assert_eq!(eval_str("0x10 & ~0x1", &scope), Value::Int(16));
+        assert_eq!(eval_str("1 < 2 && 3 == 3", &scope), Value::Int(1));
+    }
+    #[test]
+    fn test_eval_fields() {
+        let mut scope = Scope::new();
+        scope.insert("Voltage".to_owned(), Value::Float(12.5));
+        scope.insert("Current".to_owned(), Value::Int(2));
+        scope.insert("Hi".to_owned(), Value::Int(0x12));
+        scope.insert("Lo".to_owned(), Value::Int(0x34));
+        assert_eq!(eval_str("Voltage * Current", &scope), Value::Float(25.0));
+        assert_eq!(eval_str("(Hi << 8) | Lo", &scope), Value::Int(0x1234));
+    }
+    #[test]
+    fn test_eval_errors() {
+        let scope = Scope::new();
+        assert!(eval(&parse_expr("Missing + 1").unwrap(), &scope).is_err());
+        assert!(eval(&parse_expr("1 / 0").unwrap(), &scope).is_err());
+        assert!(eval(&parse_expr("1.5 | 1").unwrap(), &scope).is_err());
+        assert!(parse_expr("(1 + 2").is_err());
+        assert!(parse_expr("1 +").is_err());
+        assert!(parse_expr("1 2").is_err());
+    }
+    #[test]
+    fn test_display_expr() {
+        for input in [
+            "(Hi << 8) | Lo",
+            "10 - (4 - 3)",
+            "-(a + b) * 2.0",
+            "!a && b || c",
+        ] {
+            let expr = parse_expr(input).unwrap();
+            assert_eq!(parse_expr(&expr.to_string()).unwrap(), expr);
+        }
+        assert_eq!(parse_expr("(a * b) + c").unwrap().to_string(), "a * b + c");
+        assert_eq!(
+            parse_expr("a << (8 | b)").unwrap().to_string(),
+            "a << (8 | b)"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use crate::net::{Packet, Protocol};
+use anyhow::{bail, Context, Result};
+use std::net::IpAddr;
+
+// Filter of pcap input on the decoded headers, a small part of the language
+// of tcpdump:
+// udp and dst port 17224 and (src host 10.0.0.5 or src net 10.1.0.0/16)
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dir {
+    Src,
+    Dst,
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Protocol(Protocol),
+    Ipv4,
+    Ipv6,
+    Host(Dir, IpAddr),
+    Net(Dir, IpAddr, u8), // address and length of the prefix
+    Port(Dir, u16),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    pub fn matches(&self, packet: &Packet) -> bool {
+        // true if the address of the direction is matched
+        let dir = |dir: &Dir, matches: &dyn Fn(&std::net::SocketAddr) -> bool| match dir {
+            Dir::Src => matches(&packet.src),
+            Dir::Dst => matches(&packet.dst),
+            Dir::Any => matches(&packet.src) || matches(&packet.dst),
+        };
+        match self {
+            Filter::Protocol(protocol) => packet.protocol == *protocol,
+            Filter::Ipv4 => packet.src.is_ipv4(),
+            Filter::Ipv6 => packet.src.is_ipv6(),
+            Filter::Host(d, host) => dir(d, &|addr| addr.ip() == *host),
+            Filter::Net(d, net, prefix) => dir(d, &|addr| in_net(addr.ip(), *net, *prefix)),
+            Filter::Port(d, port) => dir(d, &|addr| addr.port() == *port),
+            Filter::Not(filter) => !filter.matches(packet),
+            Filter::And(a, b) => a.matches(packet) && b.matches(packet),
+            Filter::Or(a, b) => a.matches(packet) || b.matches(packet),
+        }
+    }
+}
+
+fn in_net(ip: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+fn tokenize(filter: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut chars = filter.chars().peekable();
+    while let Some(c) = chars.next() {
+        let operator = match c {
+            '(' | ')' | '!' => Some(c.to_string()),
+            '&' | '|' if chars.peek() == Some(&c) => {
+                chars.next();
+                Some(format!("{}{}", c, c))
+            }
+            _ => None,
+        };
+        if operator.is_some() || c.is_whitespace() {
+            if !token.is_empty() {
+                tokens.push(std::mem::take(&mut token));
+            }
+            tokens.extend(operator);
+        } else {
+            token.push(c);
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+    fn next(&mut self) -> Result<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token.context("Filter ends too early.")
+    }
+    fn or(&mut self) -> Result<Filter> {
+        let mut filter = self.and()?;
+        while matches!(self.peek(), Some("or" | "||")) {
+            self.pos += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+    fn and(&mut self) -> Result<Filter> {
+        let mut filter = self.unary()?;
+        while matches!(self.peek(), Some("and" | "&&")) {
+            self.pos += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+    fn unary(&mut self) -> Result<Filter> {
+        let token = self.next()?;
+        let dir = match token.as_str() {
+            "not" | "!" => return Ok(Filter::Not(Box::new(self.unary()?))),
+            "(" => {
+                let filter = self.or()?;
+                if self.next()? != ")" {
+                    bail!("Missing ) in filter.");
+                }
+                return Ok(filter);
+            }
+            "udp" => return Ok(Filter::Protocol(Protocol::Udp)),
+            "tcp" => return Ok(Filter::Protocol(Protocol::Tcp)),
+            "ip" => return Ok(Filter::Ipv4),
+            "ip6" => return Ok(Filter::Ipv6),
+            "src" => Dir::Src,
+            "dst" => Dir::Dst,
+            _ => {
+                self.pos -= 1;
+                Dir::Any
+            }
+        };
+        let kind = self.next()?;
+        let value = self.next()?;
+        match kind.as_str() {
+            "host" => Ok(Filter::Host(
+                dir,
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid host {} in filter.", value))?,
+            )),
+            "net" => {
+                let (net, prefix) = value
+                    .split_once('/')
+                    .with_context(|| format!("Net {} in filter needs a /prefix.", value))?;
+                let net: IpAddr = net
+                    .parse()
+                    .with_context(|| format!("Invalid net {} in filter.", value))?;
+                let max = if net.is_ipv4() { 32 } else { 128 };
+                match prefix.parse() {
+                    Ok(prefix) if prefix <= max => Ok(Filter::Net(dir, net, prefix)),
+                    _ => bail!("Invalid prefix of net {} in filter.", value),
+                }
+            }
+            "port" => Ok(Filter::Port(
+                dir,
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid port {} in filter.", value))?,
+            )),
+            _ => bail!("Unknown {} in filter.", kind),
+        }
+    }
+}
+
+pub fn parse_filter(filter: &str) -> Result<Filter> {
+    let mut parser = Parser {
+        tokens: tokenize(filter),
+        pos: 0,
+    };
+    let parsed = parser.or()?;
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected {} in filter.", token);
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let packet = Packet {
+            protocol: Protocol::Udp,
+            src: "10.0.0.5:5000".parse().unwrap(),
+            dst: "10.0.0.7:17224".parse().unwrap(),
+            payload: 0..0,
+        };
+        let matches = |filter: &str| parse_filter(filter).unwrap().matches(&packet);
+        assert!(matches("udp and dst port 17224 and src host 10.0.0.5"));
+        assert!(matches("port 5000 && ip"));
+        assert!(matches(
+            "tcp or (src net 10.0.0.0/24 and not dst host 10.0.0.5)"
+        ));
+        assert!(!matches("tcp || ip6"));
+        assert!(!matches("src port 17224"));
+        assert!(!matches("!udp"));
+        assert!(parse_filter("udp and").is_err());
+        assert!(parse_filter("dst host nothere").is_err());
+        assert!(parse_filter("(udp").is_err());
+        assert!(parse_filter("udp tcp").is_err());
+    }
+}
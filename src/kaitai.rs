@@ -0,0 +1,617 @@
+use crate::expr::parse_expr;
+use anyhow::{bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::{HashMap, HashSet};
+
+// Import of Kaitai Struct (.ksy) definitions. Only the part of Kaitai that
+// fits the mask of mview is read: a seq of integers, floats, bit fields, str
+// and bytes of fixed size and user types, with size, repeat (eos and expr),
+// if, enum and doc. Everything else is collected and reported at once.
+
+const META_KEYS: [&str; 12] = [
+    "id",
+    "title",
+    "application",
+    "file-extension",
+    "xref",
+    "license",
+    "ks-version",
+    "ks-debug",
+    "ks-opaque-types",
+    "encoding",
+    "endian",
+    "bit-endian",
+];
+const TYPE_KEYS: [&str; 6] = ["meta", "doc", "doc-ref", "seq", "types", "enums"];
+const SEQ_KEYS: [&str; 11] = [
+    "id",
+    "type",
+    "size",
+    "repeat",
+    "repeat-expr",
+    "if",
+    "enum",
+    "contents",
+    "encoding",
+    "doc",
+    "doc-ref",
+];
+
+// user types can contain themselves, but a mask can not
+const MAX_TYPE_DEPTH: usize = 16;
+
+// scalars of YAML as text, ids and expressions can be numbers or booleans
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// enums can be referenced with the path of the type that defines them
+fn last_component(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+struct Importer<'a> {
+    types: HashMap<String, &'a Mapping>,
+    enums: HashMap<String, Vec<(i128, String)>>,
+    endian: Option<bool>, // Some(true) for le, None if the file does not say
+    bit_le: bool,
+    in_bits: bool, // the last field was a bit field, the next byte field starts at a byte
+    errors: Vec<String>,
+}
+
+impl<'a> Importer<'a> {
+    // types and enums of all levels are found by their name
+    fn collect(&mut self, spec: &'a Mapping, what: &str) {
+        if let Some(enums) = spec.get("enums").and_then(Value::as_mapping) {
+            for (name, values) in enums.iter() {
+                let name = as_text(name).unwrap_or_default();
+                let names = self.enum_values(&name, values);
+                self.enums.insert(name, names);
+            }
+        }
+        if let Some(types) = spec.get("types").and_then(Value::as_mapping) {
+            for (name, type_spec) in types.iter() {
+                let name = as_text(name).unwrap_or_default();
+                match type_spec.as_mapping() {
+                    Some(type_spec) => {
+                        self.collect(type_spec, &name);
+                        self.types.insert(name, type_spec);
+                    }
+                    None => self
+                        .errors
+                        .push(format!("{}: type {} is not a mapping", what, name)),
+                }
+            }
+        }
+    }
+
+    // 0: off or 0: {id: off, doc: ...}
+    fn enum_values(&mut self, enum_name: &str, values: &Value) -> Vec<(i128, String)> {
+        let mut names = Vec::new();
+        for (value, name) in values.as_mapping().into_iter().flatten() {
+            let value = value.as_i64().map(i128::from);
+            let name = match name {
+                Value::Mapping(map) => map.get("id").and_then(as_text),
+                name => as_text(name),
+            };
+            match (value, name) {
+                (Some(value), Some(name)) => names.push((value, name)),
+                _ => self.errors.push(format!(
+                    "enum {}: values have to be integers with a name",
+                    enum_name
+                )),
+            }
+        }
+        names
+    }
+
+    fn check_keys(&mut self, map: &Mapping, allowed: &[&str], what: &str) {
+        for key in map.keys() {
+            let key = as_text(key).unwrap_or_default();
+            if !allowed.contains(&key.as_str()) {
+                self.errors
+                    .push(format!("{}: {} is not supported", what, key));
+            }
+        }
+    }
+
+    fn read_meta(&mut self, spec: &Mapping, what: &str) {
+        let meta = match spec.get("meta").and_then(Value::as_mapping) {
+            Some(meta) => meta,
+            None => return,
+        };
+        self.check_keys(meta, &META_KEYS, &format!("{} meta", what));
+        match meta.get("endian").map(|e| (e, e.as_str())) {
+            Some((_, Some("le"))) => self.endian = Some(true),
+            Some((_, Some("be"))) => self.endian = Some(false),
+            Some(_) => self
+                .errors
+                .push(format!("{} meta: endian can only be le or be", what)),
+            None => {}
+        }
+        match meta.get("bit-endian").map(|e| (e, e.as_str())) {
+            Some((_, Some("le"))) => self.bit_le = true,
+            Some((_, Some("be"))) => self.bit_le = false,
+            Some(_) => self
+                .errors
+                .push(format!("{} meta: bit-endian can only be le or be", what)),
+            None => {}
+        }
+    }
+
+    // Kaitai expressions are close to the ones of mview. Names get the prefix
+    // of the type they are in, enum values become numbers.
+    fn expr(
+        &mut self,
+        value: &Value,
+        locals: &HashSet<String>,
+        prefix: &str,
+        what: &str,
+    ) -> Option<String> {
+        let text = match value {
+            Value::Bool(b) => return Some(if *b { "1" } else { "0" }.to_owned()),
+            value => as_text(value)?,
+        };
+        let mut out = String::new();
+        let mut rest = text.as_str();
+        while let Some(c) = rest.chars().next() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || "_.:".contains(c)))
+                    .unwrap_or(rest.len());
+                let word = &rest[..len];
+                rest = &rest[len..];
+                let translated = if c.is_ascii_digit() {
+                    word.to_owned()
+                } else if let Some((enum_path, name)) = word.rsplit_once("::") {
+                    let value = self
+                        .enums
+                        .get(last_component(enum_path))
+                        .and_then(|names| names.iter().find(|(_, n)| n == name));
+                    match value {
+                        Some((value, _)) => value.to_string(),
+                        None => {
+                            self.errors
+                                .push(format!("{}: unknown enum value {}", what, word));
+                            return None;
+                        }
+                    }
+                } else if let Some(root_field) = word.strip_prefix("_root.") {
+                    root_field.to_owned()
+                } else {
+                    match word {
+                        "and" => "&&".to_owned(),
+                        "or" => "||".to_owned(),
+                        "not" => "!".to_owned(),
+                        "true" => "1".to_owned(),
+                        "false" => "0".to_owned(),
+                        _ if word.starts_with('_') => {
+                            self.errors.push(format!(
+                                "{}: {} in expressions is not supported",
+                                what, word
+                            ));
+                            return None;
+                        }
+                        _ if locals.contains(word.split('.').next().unwrap_or(word)) => {
+                            format!("{}{}", prefix, word)
+                        }
+                        _ => {
+                            self.errors
+                                .push(format!("{}: unknown field {} in expression", what, word));
+                            return None;
+                        }
+                    }
+                };
+                out = format!("{} {} ", out, translated);
+            } else if "?\"'[]".contains(c) {
+                self.errors.push(format!(
+                    "{}: conditionals, strings and arrays in expressions are not supported",
+                    what
+                ));
+                return None;
+            } else {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        match parse_expr(&out) {
+            Ok(expr) => Some(expr.to_string()),
+            Err(err) => {
+                self.errors
+                    .push(format!("{}: invalid expression {}: {:#}", what, text, err));
+                None
+            }
+        }
+    }
+
+    // u2le, s4, f8be, b3 to the type of mview and its byte order
+    fn primitive(&self, kaitai_type: &str) -> Option<(String, bool)> {
+        if let Some(bits) = kaitai_type.strip_prefix('b') {
+            let bits: usize = bits.parse().ok().filter(|b| (1..=128).contains(b))?;
+            let order = if self.bit_le { "le" } else { "be" };
+            return Some(match bits {
+                1 => (format!("bool1:{}", order), true),
+                _ => (format!("uarb:{}:{}", bits, order), true),
+            });
+        }
+        let (base, endian) = match kaitai_type.strip_suffix("le") {
+            Some(base) => (base, Some(true)),
+            None => match kaitai_type.strip_suffix("be") {
+                Some(base) => (base, Some(false)),
+                None => (kaitai_type, self.endian),
+            },
+        };
+        let mview_type = match base {
+            "u1" => return Some(("u8".to_owned(), false)),
+            "s1" => return Some(("i8".to_owned(), false)),
+            "u2" => "u16",
+            "u4" => "u32",
+            "u8" => "u64",
+            "s2" => "i16",
+            "s4" => "i32",
+            "s8" => "i64",
+            "f4" => "f32",
+            "f8" => "f64",
+            _ => return None,
+        };
+        Some(match endian {
+            Some(true) => (format!("{}:le", mview_type), false),
+            Some(false) => (format!("{}:be", mview_type), false),
+            // without a byte order --le decides
+            None => (mview_type.to_owned(), false),
+        })
+    }
+
+    // the lines of one element of a seq, without its if and repeat
+    fn element_lines(
+        &mut self,
+        attr: &Mapping,
+        name: &str,
+        locals: &HashSet<String>,
+        prefix: &str,
+        depth: usize,
+        lines: &mut Vec<String>,
+    ) {
+        let what = format!("seq {}", name);
+        let comment = match attr.get("doc").and_then(as_text) {
+            Some(doc) => format!(" # {}", doc.trim().replace('\n', " ")),
+            None => String::new(),
+        };
+        let size = attr.get("size");
+        let kaitai_type = match attr.get("type") {
+            Some(Value::String(t)) => Some(t.as_str()),
+            Some(_) => {
+                self.errors
+                    .push(format!("{}: switch-on types are not supported", what));
+                return;
+            }
+            None => None,
+        };
+        if let Some(contents) = attr.get("contents") {
+            // magic bytes, shown like bytes of their size
+            let len = match contents {
+                Value::String(s) => s.len(),
+                Value::Sequence(bytes) => bytes.len(),
+                _ => 1,
+            };
+            self.bytes_lines(name, &len.to_string(), &comment, lines);
+            return;
+        }
+        match kaitai_type {
+            None => match size.and_then(|size| self.expr(size, locals, prefix, &what)) {
+                Some(size) => self.bytes_lines(name, &size, &comment, lines),
+                None if size.is_none() => self
+                    .errors
+                    .push(format!("{}: a field without type needs a size", what)),
+                None => {}
+            },
+            Some("str") => match size.and_then(Value::as_u64) {
+                Some(size) => lines.push(format!("{}:string:{}{}", name, size, comment)),
+                None => self
+                    .errors
+                    .push(format!("{}: str needs a fixed size", what)),
+            },
+            Some(t) if t.contains('(') => self
+                .errors
+                .push(format!("{}: types with parameters are not supported", what)),
+            Some(t) => {
+                if let Some((mview_type, _)) = self.primitive(t) {
+                    if size.is_some() {
+                        self.errors
+                            .push(format!("{}: size of a {} is not supported", what, t));
+                    }
+                    let mut line = format!("{}:{}", name, mview_type);
+                    if let Some(enum_name) = attr.get("enum").and_then(as_text) {
+                        match self.enums.get(last_component(&enum_name)) {
+                            Some(names) => {
+                                let names: Vec<String> = names
+                                    .iter()
+                                    .map(|(value, name)| format!("{}={}", value, name))
+                                    .collect();
+                                line = format!("{}:enum({})", line, names.join(","));
+                            }
+                            None => self
+                                .errors
+                                .push(format!("{}: unknown enum {}", what, enum_name)),
+                        }
+                    }
+                    lines.push(format!("{}{}", line, comment));
+                } else if let Some(spec) = self.types.get(t).copied() {
+                    if size.is_some() {
+                        self.errors
+                            .push(format!("{}: size of a user type is not supported", what));
+                    }
+                    if depth >= MAX_TYPE_DEPTH {
+                        self.errors
+                            .push(format!("{}: type {} contains itself", what, t));
+                        return;
+                    }
+                    lines.push(format!("struct {{{}", comment));
+                    self.type_lines(
+                        spec,
+                        &format!("{}.", name),
+                        &format!("type {}", t),
+                        depth + 1,
+                        lines,
+                    );
+                    lines.push("}".to_owned());
+                } else {
+                    self.errors
+                        .push(format!("{}: type {} is not supported", what, t));
+                }
+            }
+        }
+    }
+
+    fn bytes_lines(&mut self, name: &str, size: &str, comment: &str, lines: &mut Vec<String>) {
+        lines.push(format!("repeat {{{}", comment));
+        lines.push(format!("{}:u8:hex", name));
+        lines.push(format!("}} times {}", size));
+    }
+
+    fn type_lines(
+        &mut self,
+        spec: &Mapping,
+        prefix: &str,
+        what: &str,
+        depth: usize,
+        lines: &mut Vec<String>,
+    ) {
+        self.check_keys(spec, &TYPE_KEYS, what);
+        // the byte order of a type is valid for its fields and the types it uses
+        let (endian, bit_le) = (self.endian, self.bit_le);
+        self.read_meta(spec, what);
+        let seq: &[Value] = match spec.get("seq") {
+            Some(Value::Sequence(seq)) => seq,
+            Some(_) => {
+                self.errors.push(format!("{}: seq is not a list", what));
+                &[]
+            }
+            None => &[],
+        };
+        let ids: Vec<String> = seq
+            .iter()
+            .enumerate()
+            .map(|(i, attr)| {
+                attr.get("id")
+                    .and_then(as_text)
+                    .unwrap_or_else(|| format!("unnamed{}", i))
+            })
+            .collect();
+        let locals: HashSet<String> = ids.iter().cloned().collect();
+        for (attr, id) in seq.iter().zip(ids.iter()) {
+            let name = format!("{}{}", prefix, id);
+            let what = format!("seq {}", name);
+            let attr = match attr.as_mapping() {
+                Some(attr) => attr,
+                None => {
+                    self.errors.push(format!("{}: is not a mapping", what));
+                    continue;
+                }
+            };
+            self.check_keys(attr, &SEQ_KEYS, &what);
+            // a byte field after bit fields starts at the next byte, like in Kaitai
+            let bits = attr
+                .get("type")
+                .and_then(Value::as_str)
+                .and_then(|t| self.primitive(t))
+                .is_some_and(|(_, bits)| bits);
+            if self.in_bits && !bits {
+                lines.push("align 1".to_owned());
+            }
+            // user types set it again for their own fields
+            self.in_bits = bits;
+            // Kaitai checks the if before it repeats
+            let condition = attr
+                .get("if")
+                .and_then(|c| self.expr(c, &locals, prefix, &what));
+            if let Some(condition) = &condition {
+                lines.push(format!("if {} {{", condition));
+            }
+            let repeat = attr.get("repeat").map(|r| (r, r.as_str()));
+            let tail = match repeat {
+                None => None,
+                Some((_, Some("eos"))) => Some("} until end".to_owned()),
+                Some((_, Some("expr"))) => match attr.get("repeat-expr") {
+                    Some(count) => self
+                        .expr(count, &locals, prefix, &what)
+                        .map(|count| format!("}} times {}", count)),
+                    None => {
+                        self.errors
+                            .push(format!("{}: repeat: expr needs a repeat-expr", what));
+                        None
+                    }
+                },
+                Some((r, _)) => {
+                    self.errors.push(format!(
+                        "{}: repeat: {} is not supported",
+                        what,
+                        as_text(r).unwrap_or_default()
+                    ));
+                    None
+                }
+            };
+            if tail.is_some() {
+                lines.push("repeat {".to_owned());
+            }
+            self.element_lines(attr, &name, &locals, prefix, depth, lines);
+            if let Some(tail) = tail {
+                lines.push(tail);
+            }
+            if condition.is_some() {
+                lines.push("}".to_owned());
+            }
+        }
+        self.endian = endian;
+        self.bit_le = bit_le;
+    }
+}
+
+// the config lines of the top level type of a .ksy file
+pub fn import_ksy(text: &str) -> Result<Vec<String>> {
+    let root: Value = serde_yaml::from_str(text).context("Syntax error in Kaitai file")?;
+    let root = root
+        .as_mapping()
+        .context("Syntax error in Kaitai file, it is not a YAML mapping.")?;
+    let mut importer = Importer {
+        types: HashMap::new(),
+        enums: HashMap::new(),
+        endian: None,
+        bit_le: false,
+        in_bits: false,
+        errors: Vec::new(),
+    };
+    importer.collect(root, "file");
+    let id = root
+        .get("meta")
+        .and_then(|meta| meta.get("id"))
+        .and_then(as_text)
+        .unwrap_or_else(|| "unnamed".to_owned());
+    let mut lines = vec![format!("# {} imported by mview from Kaitai Struct", id)];
+    importer.type_lines(root, "", "file", 0, &mut lines);
+    if !importer.errors.is_empty() {
+        bail!(
+            "The Kaitai file uses constructs mview does not support: {}",
+            importer.errors.join("; ")
+        );
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KSY: &str = "meta:
+  id: packet
+  endian: le
+seq:
+  - id: magic
+    contents: [0x4d, 0x56]
+  - id: kind
+    type: u1
+    enum: kinds
+  - id: len
+    type: u2
+    doc: number of readings
+  - id: flags
+    type: b3
+  - id: valid
+    type: b1
+  - id: temp
+    type: f4be
+    if: kind == kinds::sensor and valid
+  - id: name
+    type: str
+    size: 4
+    encoding: ASCII
+  - id: readings
+    type: reading
+    repeat: expr
+    repeat-expr: len
+  - id: rest
+    size: 2
+types:
+  reading:
+    seq:
+      - id: value
+        type: s2
+      - id: extra
+        type: u1
+        if: value < 0
+enums:
+  kinds:
+    1: sensor
+    2:
+      id: actor
+      doc: moves things
+";
+
+    #[test]
+    fn test_import_ksy() {
+        assert_eq!(
+            import_ksy(KSY).unwrap(),
+            [
+                "# packet imported by mview from Kaitai Struct",
+                "repeat {",
+                "magic:u8:hex",
+                "} times 2",
+                "kind:u8:enum(1=sensor,2=actor)",
+                "len:u16:le # number of readings",
+                "flags:uarb:3:be",
+                "valid:bool1:be",
+                "align 1",
+                "if kind == 1 && valid {",
+                "temp:f32:be",
+                "}",
+                "name:string:4",
+                "repeat {",
+                "struct {",
+                "readings.value:i16:le",
+                "if readings.value < 0 {",
+                "readings.extra:u8",
+                "}",
+                "}",
+                "} times len",
+                "repeat {",
+                "rest:u8:hex",
+                "} times 2",
+            ]
+        );
+    }
+    #[test]
+    fn test_import_ksy_unsupported() {
+        let ksy = "seq:
+  - id: name
+    type: strz
+  - id: body
+    type: u1
+    repeat: until
+  - id: x
+    type: u1
+    if: _parent.y
+instances:
+  foo:
+    pos: 4
+";
+        let err = format!("{:#}", import_ksy(ksy).unwrap_err());
+        assert!(err.contains("file: instances is not supported"), "{}", err);
+        assert!(
+            err.contains("seq name: type strz is not supported"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("seq body: repeat: until is not supported"),
+            "{}",
+            err
+        );
+        assert!(err.contains("_parent.y in expressions"), "{}", err);
+        assert!(import_ksy("- not a mapping").is_err());
+    }
+}
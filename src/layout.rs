@@ -0,0 +1,416 @@
+use crate::{
+    args::Args,
+    chunksize_by_config,
+    export::{place, Node, Placed},
+    mask::parse_mask,
+    read_config, BYTE_TO_BIT,
+};
+use anyhow::{bail, Context, Result};
+use crossterm::style::{self, Color, Stylize};
+use std::fs;
+
+// Static view of a mask: where the fields are in the chunk, as a table or
+// drawn as a bit diagram like the ones in the RFCs.
+
+const ROW_BITS: usize = 32;
+const MAX_ROWS: usize = 4096; // a diagram with more rows is of no use to anyone
+                              // size of a bit and of a row in the SVG diagram, in pixels
+const SVG_BIT: usize = 20;
+const SVG_ROW: usize = 36;
+const SVG_MARGIN: usize = 10;
+const SVG_HEADER: usize = 20;
+const SVG_CHAR: usize = 7;
+
+// a field and the overlays it is in, as number of the overlay and of the member
+type Field = (Placed, Vec<(usize, usize)>);
+
+// the fields of placed nodes, the members of an overlay in their order
+fn flatten(
+    nodes: Vec<Node>,
+    overlays: &[(usize, usize)],
+    n_overlays: &mut usize,
+    fields: &mut Vec<Field>,
+) {
+    for node in nodes {
+        match node {
+            Node::Field(placed) => fields.push((placed, overlays.to_vec())),
+            Node::Overlay(_, _, alternatives) => {
+                let overlay = *n_overlays;
+                *n_overlays += 1;
+                for (member, alternative) in alternatives.into_iter().enumerate() {
+                    let mut overlays = overlays.to_vec();
+                    overlays.push((overlay, member));
+                    flatten(alternative, &overlays, n_overlays, fields);
+                }
+            }
+            Node::Computed(_) => {}
+        }
+    }
+}
+
+// fields in different members of the same overlay overlap on purpose
+fn in_other_member(a: &Field, b: &Field) -> bool {
+    a.1.iter()
+        .any(|(overlay, member)| b.1.iter().any(|(o, m)| o == overlay && m != member))
+}
+
+// the fields of a config, with the overlays they are in
+fn placed_fields(config_lines: &[String]) -> Result<Vec<Field>> {
+    let mut nodes = Vec::new();
+    place(
+        &parse_mask(config_lines)?,
+        &mut 0,
+        &mut false,
+        "",
+        &mut nodes,
+    )?;
+    let mut fields = Vec::new();
+    flatten(nodes, &[], &mut 0, &mut fields);
+    Ok(fields)
+}
+
+// the fields of a config and for each bit of the chunk the field it belongs
+// to, overlapping fields give the bit to the first of them
+fn fields_and_owners(config_lines: &[String]) -> Result<(Vec<Field>, Vec<Option<usize>>)> {
+    let fields = placed_fields(config_lines)?;
+    let size = chunksize_by_config(config_lines)?;
+    if size == 0 {
+        bail!("The config has no fields.");
+    }
+    if size > MAX_ROWS * ROW_BITS {
+        bail!(
+            "The config is {} bits long, too long to draw, a diagram has at most {} rows of {} bits.",
+            size,
+            MAX_ROWS,
+            ROW_BITS
+        );
+    }
+    let mut owners = vec![None; size];
+    for (index, (field, _)) in fields.iter().enumerate() {
+        for owner in owners.iter_mut().skip(field.bitpos).take(field.size) {
+            owner.get_or_insert(index);
+        }
+    }
+    Ok((fields, owners))
+}
+
+// the runs of bits of a row that belong to the same field: start, end and field
+fn segments(row: &[Option<usize>]) -> Vec<(usize, usize, Option<usize>)> {
+    let mut segments: Vec<(usize, usize, Option<usize>)> = Vec::new();
+    for (bit, owner) in row.iter().enumerate() {
+        match segments.last_mut() {
+            Some((_, end, last)) if last == owner => *end = bit + 1,
+            _ => segments.push((bit, bit + 1, *owner)),
+        }
+    }
+    segments
+}
+
+// the name cut to the room there is for it
+fn fit(name: &str, width: usize) -> String {
+    name.chars().take(width).collect()
+}
+
+// the line between two rows, open where a field goes on into the next row
+fn separator(above: &[Option<usize>], below: &[Option<usize>]) -> String {
+    let inside = |bit: usize| matches!((above.get(bit), below.get(bit)), (Some(Some(a)), Some(Some(b))) if a == b);
+    let mut line = String::new();
+    for bit in 0..above.len().max(below.len()) {
+        let open = bit > 0 && inside(bit - 1) && inside(bit) && above[bit - 1] == above[bit];
+        line.push(if open { ' ' } else { '+' });
+        line.push(if inside(bit) { ' ' } else { '-' });
+    }
+    line.push('+');
+    line
+}
+
+// the bit diagram of a config in ASCII, 32 bits in a row
+pub fn ascii_diagram(config_lines: &[String]) -> Result<String> {
+    let (fields, owners) = fields_and_owners(config_lines)?;
+    let width = owners.len().min(ROW_BITS);
+    // bit numbers, the tens above the ones
+    let tens: String = (0..width)
+        .map(|bit| match bit % 10 {
+            0 => format!(" {}", bit / 10),
+            _ => "  ".to_owned(),
+        })
+        .collect();
+    let mut out = vec![
+        tens.trim_end().to_owned(),
+        (0..width).map(|bit| format!(" {}", bit % 10)).collect(),
+    ];
+    let rows: Vec<&[Option<usize>]> = owners.chunks(ROW_BITS).collect();
+    // the name of a field is written into the first box of it
+    let mut named = vec![false; fields.len()];
+    for (index, row) in rows.iter().enumerate() {
+        out.push(separator(
+            if index > 0 { rows[index - 1] } else { &[] },
+            row,
+        ));
+        let mut line = vec![' '; 2 * row.len() + 1];
+        for (start, end, owner) in segments(row) {
+            line[2 * start] = '|';
+            let Some(field) = owner else { continue };
+            if named[field] {
+                continue;
+            }
+            named[field] = true;
+            let room = 2 * (end - start) - 1;
+            let name = fit(&fields[field].0.name, room);
+            let pad = (room - name.chars().count()) / 2;
+            for (i, c) in name.chars().enumerate() {
+                line[2 * start + 1 + pad + i] = c;
+            }
+        }
+        line[2 * row.len()] = '|';
+        out.push(line.into_iter().collect());
+    }
+    out.push(separator(rows[rows.len() - 1], &[]));
+    Ok(out.join("\n") + "\n")
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// the bit diagram of a config as SVG image
+pub fn svg_diagram(config_lines: &[String]) -> Result<String> {
+    let (fields, owners) = fields_and_owners(config_lines)?;
+    let width = owners.len().min(ROW_BITS);
+    let n_rows = owners.len().div_ceil(ROW_BITS);
+    let mut out = vec![
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"monospace\" font-size=\"12\" text-anchor=\"middle\">",
+            width * SVG_BIT + 2 * SVG_MARGIN,
+            n_rows * SVG_ROW + SVG_HEADER + 2 * SVG_MARGIN
+        ),
+        "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>".to_owned(),
+    ];
+    for bit in 0..width {
+        out.push(format!(
+            "<text x=\"{}\" y=\"{}\">{}</text>",
+            SVG_MARGIN + bit * SVG_BIT + SVG_BIT / 2,
+            SVG_MARGIN + SVG_HEADER / 2,
+            bit
+        ));
+    }
+    let mut named = vec![false; fields.len()];
+    for (index, row) in owners.chunks(ROW_BITS).enumerate() {
+        let y = SVG_MARGIN + SVG_HEADER + index * SVG_ROW;
+        for (start, end, owner) in segments(row) {
+            let x = SVG_MARGIN + start * SVG_BIT;
+            let box_width = (end - start) * SVG_BIT;
+            out.push(format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>",
+                x, y, box_width, SVG_ROW
+            ));
+            let Some(field) = owner else { continue };
+            if named[field] {
+                continue;
+            }
+            named[field] = true;
+            out.push(format!(
+                "<text x=\"{}\" y=\"{}\">{}</text>",
+                x + box_width / 2,
+                y + SVG_ROW / 2 + 4,
+                xml_escape(&fit(&fields[field].0.name, box_width / SVG_CHAR))
+            ));
+        }
+    }
+    out.push("</svg>".to_owned());
+    Ok(out.join("\n") + "\n")
+}
+
+// the table of the offset and size of each field, and warnings about fields
+// that overlap or do not fit into the chunksize (0 for the size of the config)
+pub fn layout_table(config_lines: &[String], chunksize: usize) -> Result<(String, Vec<String>)> {
+    let fields = placed_fields(config_lines)?;
+    if fields.is_empty() {
+        bail!("The config has no fields.");
+    }
+    let mut rows = vec![[
+        "Field", "Type", "Byte", "Bit", "Bits", "End byte", "End bit",
+    ]
+    .map(|title| title.to_owned())];
+    let mut warnings = Vec::new();
+    for (index, (field, _)) in fields.iter().enumerate() {
+        let end = field.bitpos + field.size;
+        let val_type = match field.len {
+            0 => field.val_type.clone(),
+            len => format!("{}:{}", field.val_type, len),
+        };
+        rows.push([
+            field.name.clone(),
+            val_type,
+            (field.bitpos / BYTE_TO_BIT).to_string(),
+            (field.bitpos % BYTE_TO_BIT).to_string(),
+            field.size.to_string(),
+            (end / BYTE_TO_BIT).to_string(),
+            (end % BYTE_TO_BIT).to_string(),
+        ]);
+        for other in fields.iter().skip(index + 1) {
+            let other_end = other.0.bitpos + other.0.size;
+            if field.bitpos < other_end
+                && other.0.bitpos < end
+                && !in_other_member(&fields[index], other)
+            {
+                warnings.push(format!(
+                    "Field {} overlaps field {}.",
+                    field.name, other.0.name
+                ));
+            }
+        }
+        if chunksize > 0 && end > chunksize * BYTE_TO_BIT {
+            warnings.push(format!(
+                "Field {} ends at byte {}, bit {}, past the chunksize of {} bytes.",
+                field.name,
+                end / BYTE_TO_BIT,
+                end % BYTE_TO_BIT,
+                chunksize
+            ));
+        }
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    let mut out = String::new();
+    for row in rows.iter() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(column, (cell, width))| match column {
+                // name and type to the left, numbers to the right
+                0 | 1 => format!("{:<width$}", cell),
+                _ => format!("{:>width$}", cell),
+            })
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    Ok((out, warnings))
+}
+
+fn print_table(config_lines: &[String], chunksize: usize) -> Result<()> {
+    let (table, warnings) = layout_table(config_lines, chunksize)?;
+    print!("{}", table);
+    for warning in warnings {
+        eprintln!(
+            "{}: {}",
+            style::style("WARNING").with(Color::Yellow).bold(),
+            warning
+        );
+    }
+    Ok(())
+}
+
+// print the table of the config of the arguments, for --layout
+pub fn print_layout(args: &Args) -> Result<()> {
+    let config_lines = read_config(&args.config, &args.mask_lines, args)
+        .with_context(|| format!("Could not read config {}", args.config))?;
+    print_table(&config_lines, args.chunksize)
+}
+
+// show the layout of a config, without options as table
+pub fn layout(config_path: &str, diagram: bool, svg: &str, args: &Args) -> Result<()> {
+    let config_lines = read_config(config_path, &[], args)
+        .with_context(|| format!("Could not read config {}", config_path))?;
+    if !diagram && svg.is_empty() {
+        return print_table(&config_lines, args.chunksize);
+    }
+    if diagram {
+        print!("{}", ascii_diagram(&config_lines)?);
+    }
+    if !svg.is_empty() {
+        fs::write(svg, svg_diagram(&config_lines)?)
+            .with_context(|| format!("Could not write to {}", svg))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(config: &str) -> Vec<String> {
+        config.lines().map(|s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_ascii_diagram() {
+        let config = lines(
+            "Version:uarb:4
+IHL:uarb:4
+TOS:u8
+Length:u16
+Address:u64
+Flag:bool1",
+        );
+        assert_eq!(
+            ascii_diagram(&config).unwrap(),
+            " 0                   1                   2                   3
+ 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
++-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+|Version|  IHL  |      TOS      |            Length             |
++-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+|                            Address                            |
++                                                               +
+|                                                               |
++-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+|F|
++-+
+"
+        );
+        let svg = svg_diagram(&config).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"660\""));
+        assert!(svg.contains("<text x=\"490\" y=\"52\">Length</text>"));
+        assert!(svg.ends_with("</svg>\n"));
+        // the size depends on the data
+        assert!(ascii_diagram(&lines("repeat {\nA:u8\n} until end")).is_err());
+        // too long to draw
+        let far = lines("A:u8\n@byte 1000000000\nB:u8");
+        assert!(ascii_diagram(&far).is_err());
+        assert!(svg_diagram(&far).is_err());
+    }
+    #[test]
+    fn test_layout_table() {
+        let config = lines(
+            "Id:u16:hex
+Flags:uarb:3:le
+@byte 1
+Name:string:2
+overlay {
+Raw:u32
+Value:f32
+}",
+        );
+        let (table, warnings) = layout_table(&config, 6).unwrap();
+        assert_eq!(
+            table,
+            "Field  Type      Byte  Bit  Bits  End byte  End bit
+Id     u16          0    0    16         2        0
+Flags  uarb:3       2    0     3         2        3
+Name   string:2     1    0    16         3        0
+Raw    u32          3    0    32         7        0
+Value  f32          3    0    32         7        0
+"
+        );
+        // the members of the overlay overlap on purpose
+        assert_eq!(
+            warnings,
+            vec![
+                "Field Id overlaps field Name.",
+                "Field Flags overlaps field Name.",
+                "Field Raw ends at byte 7, bit 0, past the chunksize of 6 bytes.",
+                "Field Value ends at byte 7, bit 0, past the chunksize of 6 bytes.",
+            ]
+        );
+        // a far position takes no memory per bit
+        let (table, _) = layout_table(&lines("A:u8\n@byte 1000000000\nB:u8"), 0).unwrap();
+        assert!(table.ends_with("B      u8    1000000000    0     8  1000000001        0\n"));
+    }
+}
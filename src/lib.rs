@@ -1,8 +1,10 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use args::Args;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Local, Utc};
+use mask::{align_bitpos, mask_end, parse_mask};
+use net::Packet;
+use pcap_parser::Linktype;
 use std::fmt::{Binary, Debug, Display, UpperHex};
-use std::sync::{Arc, Mutex};
 use std::{
     fs::File,
     io::{BufRead, BufReader, Write},
@@ -11,10 +13,23 @@ use std::{
 use write::Stats;
 
 pub mod args;
+pub mod cheader;
+pub mod dbc;
+pub mod export;
+pub mod expr;
+pub mod filter;
+pub mod kaitai;
+pub mod layout;
+pub mod mask;
+pub mod net;
+pub mod profile;
 pub mod read;
+pub mod structured;
+pub mod wireshark;
 pub mod write;
 
 pub const MAX_READ_SIZE: usize = 16 * 1024;
+pub const MAX_PCAP_BUFFER: usize = 16 * 1024 * 1024; // default of --pcap-buffer
 pub const BYTE_TO_BIT: usize = 8;
 pub const HEX_LINE_SIZE: usize = 16; // how many bytes are printed in a line with --rawhex
 pub const BIN_LINE_SIZE: usize = 8; // how many bytes are printed in a line with --rawhex
@@ -24,7 +39,7 @@ pub enum PcapTs {
     Nanosecs,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Format {
     Norm,
     Hex,
@@ -49,14 +64,99 @@ fn format_number<T: Display + Debug + Binary + UpperHex>(num: T, format: Format)
     }
 }
 
-pub fn read_config(config_path: &str) -> Result<Vec<String>> {
+// the config lines of a config file and the extra lines behind them, like
+// the ones of -f and --mask, which can also be the whole config
+pub fn read_config(config_path: &str, extra_lines: &[String], args: &Args) -> Result<Vec<String>> {
+    let mut config_lines = match config_path.is_empty() {
+        true => Vec::new(),
+        false => read_config_file(config_path, args)?,
+    };
+    config_lines.extend(extra_lines.iter().cloned());
+    Ok(config_lines)
+}
+
+fn read_config_file(config_path: &str, args: &Args) -> Result<Vec<String>> {
+    // the mask of a C header is the struct chosen with --struct
+    if config_path.ends_with(".h") {
+        let header = std::fs::read_to_string(config_path)
+            .with_context(|| format!("Could not read header {}", config_path))?;
+        return cheader::import_c(&header, &args.select, args.little_endian);
+    }
+    if config_path.ends_with(".ksy") {
+        let ksy = std::fs::read_to_string(config_path)
+            .with_context(|| format!("Could not read Kaitai file {}", config_path))?;
+        return kaitai::import_ksy(&ksy);
+    }
+    // the mask of a DBC file is the message chosen with --message
+    if config_path.ends_with(".dbc") {
+        let dbc = std::fs::read_to_string(config_path)
+            .with_context(|| format!("Could not read DBC file {}", config_path))?;
+        return dbc::import_dbc(&dbc, &args.select);
+    }
+    // TOML, YAML and JSON configs are turned into config lines
+    if let Some(syntax) = structured::Syntax::from_path(config_path) {
+        return structured::read_structured(config_path, syntax);
+    }
     Ok(BufReader::new(File::open(config_path)?)
         .lines()
-        .flatten()
-        .filter(|l| !l.starts_with('#'))
+        .map_while(Result::ok)
+        .filter(|l| !l.starts_with('#') && !l.starts_with("@option"))
         .collect())
 }
 
+// the options a config carries in lines like `#!mview --le --offset 42` or
+// `@option --pcap`, imported and structured configs have none
+pub fn config_options(config_path: &str) -> Result<Vec<String>> {
+    if [".h", ".ksy", ".dbc"]
+        .iter()
+        .any(|ext| config_path.ends_with(ext))
+        || structured::Syntax::from_path(config_path).is_some()
+    {
+        return Ok(Vec::new());
+    }
+    let mut options = Vec::new();
+    for line in BufReader::new(File::open(config_path)?)
+        .lines()
+        .map_while(Result::ok)
+    {
+        if let Some(line) = line
+            .strip_prefix("#!mview")
+            .or_else(|| line.strip_prefix("@option"))
+        {
+            options.extend(split_options(line)?);
+        }
+    }
+    Ok(options)
+}
+
+// splits options like a shell does, quotes keep an option with spaces together
+// and a backslash escapes the next character
+fn split_options(line: &str) -> Result<Vec<String>> {
+    let mut options = Vec::new();
+    let mut option: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                let option = option.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(quote) if quote == c => break,
+                        Some('\\') if c == '"' => option.extend(chars.next()),
+                        Some(inner) => option.push(inner),
+                        None => bail!("Missing closing {} in the options of the config.", c),
+                    }
+                }
+            }
+            '\\' => option.get_or_insert_with(String::new).extend(chars.next()),
+            c if c.is_whitespace() => options.extend(option.take()),
+            c => option.get_or_insert_with(String::new).push(c),
+        }
+    }
+    options.extend(option);
+    Ok(options)
+}
+
 pub fn size_in_bits<T>() -> usize {
     size_of::<T>() * BYTE_TO_BIT
 }
@@ -126,36 +226,34 @@ pub fn print_raw_ascii(writer: &mut dyn Write, chunk: &[u8], hex_lines: usize) -
     Ok(())
 }
 
-pub fn print_timestamp(
-    writer: &mut dyn Write,
-    args: &Args,
-    pcap_ts: &Arc<Mutex<PcapTs>>,
-    pcapmsgheader: &PcapMsgHeader,
-) -> Result<()> {
-    if !args.pcap {
-        writer
-            .write_fmt(format_args!("{}\n", chrono::offset::Local::now()))
-            .context("Could now write to writer")?;
-    } else {
-        let factor = match *pcap_ts.lock().unwrap() {
-            // can use unwrap, lock() is blocking
-            PcapTs::Microsecs => 1_000,
-            PcapTs::Nanosecs => 1_000_000,
-        };
-        writer
-            .write_fmt(format_args!(
-                "{}\n",
-                DateTime::<Utc>::from_utc(
-                    NaiveDateTime::from_timestamp_opt(
-                        pcapmsgheader.timestamp_s.into(),
-                        pcapmsgheader.timestamp_us * factor
-                    )
-                    .unwrap_or_default(),
-                    Utc
-                )
-            ))
-            .context("Could now write to writer")?;
+pub fn print_timestamp(writer: &mut dyn Write, args: &Args, message: &Message) -> Result<()> {
+    match message.timestamp {
+        // the time of the capture, in its precision
+        Some(timestamp) if args.pcap => writer.write_fmt(format_args!(
+            "{}{}\n",
+            timestamp,
+            match message.is_truncated() {
+                true => format!(
+                    " (truncated, captured {} of {} bytes)",
+                    message.caplen, message.origlen
+                ),
+                false => String::new(),
+            }
+        )),
+        // the time the message was received
+        Some(timestamp) => writer.write_fmt(format_args!("{}\n", timestamp.with_timezone(&Local))),
+        None => writer.write_fmt(format_args!("{}\n", Local::now())),
+    }
+    .context("Could now write to writer")?;
+    Ok(())
+}
+
+pub fn print_addresses(writer: &mut dyn Write, packet: Option<&Packet>) -> Result<()> {
+    match packet {
+        Some(packet) => writer.write_fmt(format_args!("{}\n", packet)),
+        None => writer.write_all(b"no UDP or TCP packet\n"),
     }
+    .context("Could now write to writer")?;
     Ok(())
 }
 
@@ -185,13 +283,22 @@ pub fn count_lines(args: &Args, stats: &Stats, n_conf_lines: usize) -> u16 {
     if args.timestamp {
         extra_lines += 1;
     };
+    if args.print_addresses {
+        extra_lines += 1;
+    };
     if args.print_statistics {
         extra_lines += 5;
     };
     if args.print_bitpos {
         extra_lines += n_conf_lines as u16
     };
-    if args.rawbin || args.rawhex || args.timestamp || args.print_statistics || args.rawascii {
+    if args.rawbin
+        || args.rawhex
+        || args.timestamp
+        || args.print_addresses
+        || args.print_statistics
+        || args.rawascii
+    {
         extra_lines += 1;
     }
     extra_lines
@@ -199,15 +306,17 @@ pub fn count_lines(args: &Args, stats: &Stats, n_conf_lines: usize) -> u16 {
 
 pub fn print_additional(
     args: &Args,
-    pcapmsgheader: &PcapMsgHeader,
-    pcap_ts: &Arc<Mutex<PcapTs>>,
+    message: &Message,
     stats: &Stats,
     writer: &mut dyn Write,
     chunk: &[u8],
     chunksize: usize,
 ) -> Result<()> {
     if args.timestamp {
-        print_timestamp(writer, args, pcap_ts, pcapmsgheader)?;
+        print_timestamp(writer, args, message)?;
+    }
+    if args.print_addresses {
+        print_addresses(writer, message.packet.as_ref())?;
     }
     if args.print_statistics {
         print_statistics(stats, writer, chunksize)?;
@@ -221,7 +330,8 @@ pub fn print_additional(
     if args.rawascii {
         print_raw_ascii(writer, chunk, stats.hex_lines)?;
     }
-    if args.rawbin || args.rawhex || args.timestamp || args.print_statistics {
+    if args.rawbin || args.rawhex || args.timestamp || args.print_addresses || args.print_statistics
+    {
         writer
             .write_all(b"\n")
             .context("Could now write to writer")?;
@@ -232,14 +342,24 @@ pub fn print_additional(
 pub fn print_statistics(stats: &Stats, writer: &mut dyn Write, chunksize: usize) -> Result<()> {
     writer
         .write_fmt(format_args!(
-            "Message no: {}\nMessage length: {} bytes\nChunk length: {} bytes\nCurrent chunk in this message: {}\nChunk starts at byte {} of message\n",
-            stats.message_count, stats.message_len, chunksize, stats.chunk_count, stats.chunk_start
+            "Message no: {}{}\nMessage length: {} bytes{}\nChunk length: {} bytes\nCurrent chunk in this message: {}\nChunk starts at byte {} of message\n",
+            stats.message_count,
+            match stats.truncated_count {
+                0 => String::new(),
+                n => format!(", {} truncated", n),
+            },
+            stats.message_len,
+            if stats.truncated { " (truncated)" } else { "" },
+            chunksize,
+            stats.chunk_count,
+            stats.chunk_start
         ))
         .context("Could now write to writer")?;
     Ok(())
 }
 
-pub fn parse_config_line(conf_line: &str) -> Result<(&str, &str, Format, usize)> {
+// splits a config line into field name, type and the options behind the type
+fn split_config_line(conf_line: &str) -> Result<(&str, &str, &str)> {
     // discard comments and whitespaces
     let line = match conf_line.split_once('#') {
         Some(s) => s.0,
@@ -255,65 +375,203 @@ pub fn parse_config_line(conf_line: &str) -> Result<(&str, &str, Format, usize)>
         .context("Syntax error in config, could not find : in line.")?;
     let (val_type, rest) = match rest.split_once(':') {
         Some(s) => (s.0, s.1),
-        None => (rest, "0"),
+        None => (rest, ""),
     };
-    // at this point, rest could be a letter (to print in hex or binary)
-    // or a number (for stringlength)
-    let form = Format::from_str(rest);
+    Ok((fieldname, val_type, rest))
+}
+
+// options like unit=V only change how a value is shown, not how it is read
+fn is_field_attribute(option: &str) -> bool {
+    option.contains('=') || option.starts_with("enum(")
+}
+
+// le / be behind the type set the byte order of a single field, true is little endian
+fn byte_order_option(option: &str) -> Option<bool> {
+    match option.to_lowercase().as_str() {
+        "le" | "intel" => Some(true),
+        "be" | "motorola" => Some(false),
+        _ => None,
+    }
+}
+
+// The byte order of a field if the config line sets one, otherwise the one of
+// the arguments applies. For iarb / uarb it sets how the bits are counted:
+// le counts from the least significant bit of each byte and the first bit is
+// the least significant bit of the value (like an Intel signal in CAN or a
+// bitfield on a little endian machine), be counts from the most significant
+// bit and the first bit is the most significant one of the value.
+pub fn parse_byte_order(conf_line: &str) -> Result<Option<bool>> {
+    let (_, _, rest) = split_config_line(conf_line)?;
+    Ok(rest.split(':').filter_map(byte_order_option).next_back())
+}
+
+pub fn parse_config_line(conf_line: &str) -> Result<(&str, &str, Format, usize)> {
+    let (fieldname, val_type, rest) = split_config_line(conf_line)?;
+    // every option in rest is separated by a :, it could be a letter (to
+    // print in hex or binary), a number (for stringlength) or an attribute
+    let mut form = Format::Norm;
     let mut len = 0;
-    if let Ok(n) = rest.parse() {
-        len = n;
+    for option in rest.split(':').filter(|o| !o.is_empty()) {
+        if let Ok(n) = option.parse() {
+            len = n;
+        } else if !is_field_attribute(option) && byte_order_option(option).is_none() {
+            form = Format::from_str(option);
+        }
     }
     Ok((fieldname, val_type, form, len))
 }
 
+// the attributes of a field, they change how its value is printed
+#[derive(Debug, Default, PartialEq)]
+pub struct FieldAttrs {
+    pub unit: Option<String>,
+    pub names: Vec<(i128, String)>, // from enum(0=Off,1=On)
+    pub scale: Option<f64>,         // the value is raw value * scale + offset
+    pub offset: Option<f64>,
+}
+
+impl FieldAttrs {
+    pub fn is_empty(&self) -> bool {
+        self.unit.is_none() && self.names.is_empty() && !self.is_scaled()
+    }
+    pub fn is_scaled(&self) -> bool {
+        self.scale.is_some() || self.offset.is_some()
+    }
+    pub fn scaled(&self, raw: f64) -> f64 {
+        raw * self.scale.unwrap_or(1.0) + self.offset.unwrap_or(0.0)
+    }
+    pub fn name_of(&self, value: i128) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|(v, _)| *v == value)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+pub fn parse_enum_value(s: &str) -> Option<i128> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = s.strip_prefix("0x") {
+        i128::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        i128::from_str_radix(bin, 2).ok()?
+    } else {
+        s.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+pub fn parse_field_attrs(conf_line: &str) -> Result<FieldAttrs> {
+    let (fieldname, _, rest) = split_config_line(conf_line)?;
+    let mut attrs = FieldAttrs::default();
+    for option in rest.split(':').filter(|o| is_field_attribute(o)) {
+        if let Some(list) = option.strip_prefix("enum(") {
+            let list = list.strip_suffix(')').with_context(|| {
+                format!(
+                    "Syntax error in config, missing ) in enum of {}.",
+                    fieldname
+                )
+            })?;
+            for entry in list.split(',').filter(|e| !e.is_empty()) {
+                let (value, name) = entry
+                    .split_once('=')
+                    .and_then(|(v, n)| Some((parse_enum_value(v)?, n)))
+                    .with_context(|| {
+                        format!(
+                            "Syntax error in config, enum entry \"{}\" of {} is not value=name.",
+                            entry, fieldname
+                        )
+                    })?;
+                attrs.names.push((value, name.to_owned()));
+            }
+        } else if let Some(unit) = option.strip_prefix("unit=") {
+            attrs.unit = Some(unit.to_owned());
+        } else if let Some(scale) = option.strip_prefix("scale=") {
+            attrs.scale = Some(scale.parse().with_context(|| {
+                format!("Syntax error in config, invalid scale of {}.", fieldname)
+            })?);
+        } else if let Some(offset) = option.strip_prefix("offset=") {
+            attrs.offset = Some(offset.parse().with_context(|| {
+                format!("Syntax error in config, invalid offset of {}.", fieldname)
+            })?);
+        } else {
+            bail!(
+                "Syntax error in config, unknown attribute \"{}\" of {}.",
+                option,
+                fieldname
+            );
+        }
+    }
+    Ok(attrs)
+}
+
+// size of a field in bits, None if the type is unknown
+pub fn field_size_in_bits(val_type: &str, len: usize) -> Option<usize> {
+    let val_type = val_type.to_lowercase(); // don't care about type
+    match val_type.as_str() {
+        "bool1" => Some(1),
+        "bool8" | "u8" | "i8" => Some(size_in_bits::<u8>()),
+        "u16" | "i16" => Some(size_in_bits::<u16>()),
+        "u32" | "i32" | "f32" => Some(size_in_bits::<u32>()),
+        "u64" | "i64" | "f64" => Some(size_in_bits::<u64>()),
+        "u128" | "i128" => Some(size_in_bits::<u128>()),
+        "string" | "bytegap" => Some(len * size_in_bits::<u8>()),
+        "iarb" | "uarb" => Some(len),
+        "bitgap" => Some(len),
+        _ => None,
+    }
+}
+
 // calculate the size of a chunk using the config, returns bits!
+// With positioning directives the fields are not necessarily sequential,
+// so the size is the end of the field that reaches furthest into the chunk.
 pub fn chunksize_by_config(config_lines: &[String]) -> Result<usize> {
+    let items = parse_mask(config_lines)?;
+    let mut bitpos = 0;
     let mut bitlength = 0;
-    for conf_line in config_lines.iter() {
-        let (_, val_type, _, len) = parse_config_line(conf_line)?;
-        let val_type = val_type.to_lowercase(); // don't care about type
-        match val_type.as_str() {
-            "bool1" => bitlength += 1,
-            "bool8" | "u8" | "i8" => bitlength += size_in_bits::<u8>(),
-            "u16" | "i16" => bitlength += size_in_bits::<u16>(),
-            "u32" | "i32" | "f32" => bitlength += size_in_bits::<u32>(),
-            "u64" | "i64" | "f64" => bitlength += size_in_bits::<u64>(),
-            "u128" | "i128" => bitlength += size_in_bits::<u128>(),
-            "string" | "bytegap" => bitlength += len * size_in_bits::<u8>(),
-            "iarb" | "uarb" => bitlength += len,
-            "bitgap" => bitlength += len,
-            _ => eprintln!("unknown type"),
+    let mut repr_c = false;
+    let mut max_alignment = 1;
+    mask_end(
+        &items,
+        &mut bitpos,
+        0,
+        &mut repr_c,
+        &mut bitlength,
+        &mut max_alignment,
+    )?;
+    // like a C compiler, pad the struct to a multiple of its largest alignment
+    Ok(align_bitpos(bitlength, 0, max_alignment))
+}
+
+// a message from the read thread to the write thread: a packet of pcap input
+// or what was read at once from raw input
+#[derive(Debug, Default)]
+pub struct Message {
+    pub payload: Vec<u8>,
+    pub timestamp: Option<DateTime<Utc>>, // of the capture or when it was received
+    pub caplen: u32,                      // bytes captured of the packet
+    pub origlen: u32,                     // bytes of the packet on the wire
+    pub linktype: Option<Linktype>,       // of pcap input
+    pub packet: Option<Packet>,           // the addresses, with --strip-headers or --addresses
+}
+
+impl Message {
+    // a message of raw input, received now
+    pub fn received(payload: Vec<u8>) -> Self {
+        let len = payload.len() as u32;
+        Message {
+            payload,
+            timestamp: Some(Utc::now()),
+            caplen: len,
+            origlen: len,
+            ..Default::default()
         }
     }
-    Ok(bitlength)
-}
-
-#[derive(Default)]
-pub struct PcapMsgHeader {
-    timestamp_s: u32,
-    timestamp_us: u32,
-    _caplen: u32,
-    _origlen: u32,
-}
-
-impl PcapMsgHeader {
-    pub fn new(buffer: &mut [u8]) -> Self {
-        let mut tsbuf = [0u8; 4];
-        tsbuf.clone_from_slice(&buffer[..=3]);
-        let timestamp_s = u32::from_le_bytes(tsbuf);
-        tsbuf.clone_from_slice(&buffer[4..=7]);
-        let timestamp_us = u32::from_le_bytes(tsbuf);
-        tsbuf.clone_from_slice(&buffer[8..=11]);
-        let _caplen = u32::from_le_bytes(tsbuf);
-        tsbuf.clone_from_slice(&buffer[12..=15]);
-        let _origlen = u32::from_le_bytes(tsbuf);
-        PcapMsgHeader {
-            timestamp_s,
-            timestamp_us,
-            _caplen,
-            _origlen,
-        }
+    // the packet was cut off by the snaplen of the capture
+    pub fn is_truncated(&self) -> bool {
+        self.caplen < self.origlen
     }
 }
 
@@ -502,6 +760,83 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
         assert_eq!(chunksize_by_config(&config_lines).unwrap(), 135);
     }
 
+    #[test]
+    fn test_chunksize_by_config_position() {
+        // a jump behind the last field makes the chunk longer, a jump back does not shorten it
+        let config = "Field0:u8
+@0x10
+Field1:u16
+@bit 4
+Field2:uarb:4";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        assert_eq!(chunksize_by_config(&config_lines).unwrap(), 18 * 8);
+    }
+    #[test]
+    fn test_chunksize_by_config_align() {
+        let config = "Field0:u8
+align 4
+Field1:u8
+align 4";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        assert_eq!(chunksize_by_config(&config_lines).unwrap(), 8 * 8);
+    }
+    #[test]
+    fn test_chunksize_by_config_repr_c() {
+        // like struct { uint8_t a; uint32_t b; uint16_t c; } -> 1 + 3 pad + 4 + 2 + 2 pad
+        let config = "repr(C)
+Field0:u8
+Field1:u32
+Field2:u16";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        assert_eq!(chunksize_by_config(&config_lines).unwrap(), 12 * 8);
+    }
+    #[test]
+    fn test_chunksize_by_config_overlay() {
+        // only the largest member of an overlay counts
+        let config = "Field0:u8
+overlay {
+Raw:u32
+Short:u16
+Text:String:6
+}
+Field1:u8";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        assert_eq!(chunksize_by_config(&config_lines).unwrap(), 8 * 8);
+    }
+
+    #[test]
+    fn test_config_options() {
+        let path = std::env::temp_dir().join(format!(
+            "mview_test_config_options_{}.conf",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "#!mview --little-endian --offset 42\n# a comment\nLen:u16\n@option --pcap\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            config_options(path).unwrap(),
+            vec!["--little-endian", "--offset", "42", "--pcap"]
+        );
+        // quotes keep a value with spaces together
+        assert_eq!(
+            split_options(r#" --filter 'udp and port 1' --name "a \"b\"" c\ d ''"#).unwrap(),
+            vec!["--filter", "udp and port 1", "--name", "a \"b\"", "c d", ""]
+        );
+        assert!(split_options("--filter 'udp").is_err());
+        // the options are no fields of the mask
+        let config_lines =
+            read_config_file(path, &Args::parse_from(["mview", "-c", path])).unwrap();
+        assert_eq!(config_lines, vec!["Len:u16"]);
+        // the command line overrides the config
+        let args = Args::parse_from(["mview", "-c", path, "--offset", "1"]);
+        assert!(args.little_endian && args.pcap);
+        assert_eq!(args.offset, 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_size_in_bits() {
         assert_eq!(size_in_bits::<u16>(), 16);
@@ -518,6 +853,7 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -529,11 +865,22 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             little_endian: false,
             timestamp: false,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: false,
             print_bitpos: false,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         };
         let stats = Stats {
             message_count: 0,
@@ -542,6 +889,8 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             chunk_start: 0,
             hex_lines: 0,
             bin_lines: 3,
+            truncated: false,
+            truncated_count: 0,
         };
         assert_eq!(count_lines(&args, &stats, 2), 7);
     }
@@ -556,6 +905,7 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -567,11 +917,22 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             little_endian: false,
             timestamp: false,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: false,
             print_bitpos: false,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         };
         let stats = Stats {
             message_count: 0,
@@ -580,6 +941,8 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             chunk_start: 0,
             hex_lines: 3,
             bin_lines: 0,
+            truncated: false,
+            truncated_count: 0,
         };
         assert_eq!(count_lines(&args, &stats, 2), 7);
     }
@@ -594,6 +957,7 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -605,11 +969,22 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             little_endian: false,
             timestamp: false,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: false,
             print_bitpos: false,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         };
         let stats = Stats {
             message_count: 0,
@@ -618,6 +993,8 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             chunk_start: 0,
             hex_lines: 3,
             bin_lines: 0,
+            truncated: false,
+            truncated_count: 0,
         };
         assert_eq!(count_lines(&args, &stats, 2), 7);
     }
@@ -631,6 +1008,7 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -642,11 +1020,22 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             little_endian: false,
             timestamp: false,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: false,
             print_bitpos: true,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         };
         let stats = Stats {
             message_count: 0,
@@ -655,6 +1044,8 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             chunk_start: 0,
             hex_lines: 99,
             bin_lines: 99,
+            truncated: false,
+            truncated_count: 0,
         };
         assert_eq!(count_lines(&args, &stats, 2), 5);
     }
@@ -668,6 +1059,7 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -679,11 +1071,22 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             little_endian: false,
             timestamp: false,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: true,
             print_bitpos: false,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         };
         let stats = Stats {
             message_count: 0,
@@ -692,6 +1095,8 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             chunk_start: 0,
             hex_lines: 0,
             bin_lines: 3,
+            truncated: false,
+            truncated_count: 0,
         };
         assert_eq!(count_lines(&args, &stats, 2), 9);
     }
@@ -705,6 +1110,7 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -716,11 +1122,22 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             little_endian: false,
             timestamp: true,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: false,
             print_bitpos: false,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         };
         let stats = Stats {
             message_count: 0,
@@ -729,6 +1146,8 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             chunk_start: 0,
             hex_lines: 0,
             bin_lines: 3,
+            truncated: false,
+            truncated_count: 0,
         };
         assert_eq!(count_lines(&args, &stats, 2), 5);
     }
@@ -799,6 +1218,26 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
         assert_eq!(parsed_line.2, Format::Norm);
     }
     #[test]
+    fn test_parse_config_line_attributes() {
+        let conf_line = "Testfield:u16:hex:unit=km/h:enum(0=Off,0x10=On,-1=Err) # speed";
+        let parsed_line = parse_config_line(conf_line).unwrap();
+        assert_eq!(parsed_line.0, "Testfield");
+        assert_eq!(parsed_line.1, "u16");
+        assert_eq!(parsed_line.2, Format::Hex);
+        let attrs = parse_field_attrs(conf_line).unwrap();
+        assert_eq!(attrs.unit, Some("km/h".to_owned()));
+        assert_eq!(attrs.name_of(16), Some("On"));
+        assert_eq!(attrs.name_of(-1), Some("Err"));
+        assert_eq!(attrs.name_of(2), None);
+        assert!(parse_field_attrs("Testfield:u8:enum(0=Off").is_err());
+        assert!(parse_field_attrs("Testfield:u8:enum(Off)").is_err());
+        assert!(parse_field_attrs("Testfield:u8:size=3").is_err());
+        let attrs = parse_field_attrs("Testfield:u8:scale=0.5:offset=-40").unwrap();
+        assert_eq!(attrs.scaled(100.0), 10.0);
+        assert!(parse_field_attrs("Testfield:u8:scale=x").is_err());
+        assert!(parse_field_attrs("Testfield:string:4").unwrap().is_empty());
+    }
+    #[test]
     fn test_print_raw_hex() {
         let chunk: [u8; 20] = [
             0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x01, 0x02, 0x03, 0x04,
@@ -828,6 +1267,7 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -839,20 +1279,46 @@ Field14(uarb4):uarb:4"; // should sum up to 135 bits
             little_endian: false,
             timestamp: true,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: false,
             print_bitpos: false,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         };
-        let pcapheader: PcapMsgHeader = Default::default();
-        let pcap_ts = Arc::new(Mutex::new(PcapTs::Microsecs));
+        let message = Message::received(vec![1, 2, 3]);
         let mut output = Vec::new();
-        print_timestamp(&mut output, &args, &pcap_ts, &pcapheader).unwrap();
+        print_timestamp(&mut output, &args, &message).unwrap();
         let output = String::from_utf8(output).unwrap();
         let dt = Local
             .datetime_from_str(&output, "%Y-%m-%d %H:%M:%S%.f %:z\n")
             .unwrap();
         assert!(chrono::offset::Local::now() - dt < Duration::seconds(10));
     }
+    #[test]
+    fn test_print_timestamp_pcap() {
+        let args = Args::parse_from(["mview", "-f", "Len:u16", "--pcap", "-t"]);
+        let message = Message {
+            timestamp: Some(Utc.timestamp_opt(1_700_000_000, 123_456_789).unwrap()),
+            caplen: 60,
+            origlen: 1514,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        print_timestamp(&mut output, &args, &message).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "2023-11-14 22:13:20.123456789 UTC (truncated, captured 60 of 1514 bytes)\n"
+        );
+    }
 }
@@ -1,27 +1,52 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossbeam::channel::bounded;
-use mview::{args::Args, read, write, PcapTs};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use mview::{
+    args::{Args, Subcommand},
+    export, layout, read, structured, write,
+};
+use std::{process, thread};
+
+const EXIT_DAMAGED_INPUT: i32 = 2;
 
 fn main() -> Result<()> {
     // get args
     let args = Args::parse();
+    match &args.subcommand {
+        Some(Subcommand::Convert { infile, outfile }) => {
+            return structured::convert(infile, outfile, &args)
+        }
+        Some(Subcommand::ImportC { header, outfile }) => {
+            return structured::import_c(header, outfile, &args)
+        }
+        Some(Subcommand::Export {
+            lang,
+            config,
+            outfile,
+            udp_port,
+        }) => return export::export(config, outfile, *lang, *udp_port, &args),
+        Some(Subcommand::Layout {
+            config,
+            diagram,
+            svg,
+        }) => return layout::layout(config, *diagram, svg, &args),
+        None => {}
+    }
+    if args.layout {
+        return layout::print_layout(&args);
+    }
     let (write_tx, write_rx) = bounded(1024);
 
-    // the mutex for wether the timestamp in the PCAP file is in
-    // nanoseconds or microseconds
-    let pcap_ts = Arc::new(Mutex::new(PcapTs::Microsecs));
-
-    thread::scope(|s| {
-        let pcap_ts_read = Arc::clone(&pcap_ts);
-        let pcap_ts_write = Arc::clone(&pcap_ts);
-        let read_handle = s.spawn(|| read::read_loop(&args, write_tx, pcap_ts_read));
-        let write_handle = s.spawn(|| write::write_loop(&args, write_rx, pcap_ts_write));
+    let report = thread::scope(|s| -> Result<read::ReadReport> {
+        let read_handle = s.spawn(|| read::read_loop(&args, write_tx));
+        let write_handle = s.spawn(|| write::write_loop(&args, write_rx));
         let read_io_result = read_handle.join().expect("Unable to join read thread");
         let write_io_result = write_handle.join().expect("Unable to join write thread");
-        read_io_result.expect("Error during read thread");
-        write_io_result.expect("Error during write thread");
-    });
+        write_io_result.context("Error during write thread")?;
+        read_io_result.context("Error during read thread")
+    })?;
+    if report.is_damaged() {
+        // the output misses what was skipped of the input
+        process::exit(EXIT_DAMAGED_INPUT);
+    }
     Ok(())
 }
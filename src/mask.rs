@@ -0,0 +1,635 @@
+use crate::{
+    expr::{parse_expr, Expr, Value},
+    field_size_in_bits, parse_config_line, Format, BYTE_TO_BIT,
+};
+use anyhow::{bail, Context, Result};
+
+// how often the items of repeat blocks with a fixed count may be repeated,
+// nested blocks multiply, so walking the mask without data stays cheap
+pub const MAX_REPETITIONS: i128 = 1_000_000;
+
+// Directives are config lines that do not describe a field but move the
+// position inside the chunk. They are told apart from field lines by not
+// containing a ':'.
+#[derive(Debug, PartialEq)]
+pub enum Directive {
+    Position(usize), // jump to an absolute bit position (relative to the start of the mask)
+    Align(usize),    // pad to the next multiple of n bytes
+    Repr(bool),      // switch automatic C-ABI alignment on (repr(C)) or off (repr(packed))
+}
+
+// A mask is a tree of items. Most config lines are plain fields, blocks
+// like `overlay {` ... `}` group several lines.
+#[derive(Debug, PartialEq)]
+pub enum MaskItem {
+    Field(String),
+    Directive(Directive),
+    Overlay(Vec<MaskItem>), // every member starts at the same position
+    Struct(Vec<MaskItem>),  // members follow each other, like plain config lines
+    Repeat(Option<Expr>, Vec<MaskItem>), // repeated n times, or until the end of the chunk
+    If(Expr, Vec<MaskItem>), // only decoded if the expression is true
+    Tlv(String, Vec<(usize, Vec<MaskItem>)>), // tlv config line and the sub-masks per type
+    Computed(String, Expr, Format), // name = expression over other fields, takes no bits
+}
+
+// where the walk through a mask currently is inside the chunk
+pub struct MaskCursor {
+    pub bitpos: usize,
+    pub mask_start: usize, // positions and alignment are counted from here
+    pub repr_c: bool,
+}
+
+impl MaskCursor {
+    pub fn new(mask_start: usize) -> Self {
+        MaskCursor {
+            bitpos: mask_start,
+            mask_start,
+            repr_c: false,
+        }
+    }
+}
+
+enum BlockKind {
+    Overlay,
+    Struct,
+    Repeat,
+    If(Expr),
+    Tlv(String, Vec<(usize, Vec<MaskItem>)>),
+    Case(usize),
+}
+
+pub fn strip_comment(conf_line: &str) -> &str {
+    match conf_line.split_once('#') {
+        Some(s) => s.0,
+        None => conf_line,
+    }
+    .trim()
+}
+
+pub fn parse_number(s: &str) -> Option<usize> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        usize::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        usize::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+pub fn parse_directive(conf_line: &str) -> Result<Option<Directive>> {
+    // discard comments and whitespaces
+    let line = strip_comment(conf_line);
+    if line.contains(':') {
+        return Ok(None);
+    }
+    if let Some(pos) = line.strip_prefix('@') {
+        let pos = pos.trim();
+        let bitpos = if let Some(bits) = pos.strip_prefix("bit") {
+            parse_number(bits).context("Syntax error in config, invalid bit position.")?
+        } else {
+            let bytes = pos.strip_prefix("byte").unwrap_or(pos);
+            parse_number(bytes).context("Syntax error in config, invalid byte position.")?
+                * BYTE_TO_BIT
+        };
+        return Ok(Some(Directive::Position(bitpos)));
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["align", n] => {
+            let n = parse_number(n).context("Syntax error in config, invalid alignment.")?;
+            if n == 0 {
+                bail!("Syntax error in config, alignment must be at least 1 byte.");
+            }
+            Ok(Some(Directive::Align(n)))
+        }
+        ["repr", "C" | "c"] | ["repr(C)" | "repr(c)"] => Ok(Some(Directive::Repr(true))),
+        ["repr", "packed"] | ["repr(packed)"] => Ok(Some(Directive::Repr(false))),
+        _ => Ok(None),
+    }
+}
+
+pub fn is_tlv_type(val_type: &str) -> bool {
+    let val_type = val_type.to_lowercase();
+    val_type == "tlv" || val_type.starts_with("tlv(")
+}
+
+// returns the size of the type and of the length field of a tlv in bits,
+// e.g. tlv(type=u8,len=u16). Both default to u8.
+pub fn parse_tlv_type(val_type: &str) -> Result<(usize, usize)> {
+    let val_type = val_type.to_lowercase();
+    let mut type_bits = 8;
+    let mut len_bits = 8;
+    let params = val_type
+        .strip_prefix("tlv")
+        .unwrap_or_default()
+        .trim_start_matches('(')
+        .trim_end_matches(')');
+    for param in params.split(',').filter(|p| !p.is_empty()) {
+        let (key, int_type) = param
+            .split_once('=')
+            .context("Syntax error in config, tlv parameters look like type=u8,len=u16.")?;
+        let bits = match int_type {
+            "u8" => 8,
+            "u16" => 16,
+            "u32" => 32,
+            "u64" => 64,
+            _ => bail!("Syntax error in config, tlv fields can only be u8, u16, u32 or u64."),
+        };
+        match key {
+            "type" | "t" => type_bits = bits,
+            "len" | "length" | "l" => len_bits = bits,
+            _ => bail!("Syntax error in config, unknown tlv parameter {}.", key),
+        }
+    }
+    Ok((type_bits, len_bits))
+}
+
+// computed fields look like `Power = Voltage * Current` or `Id:hex = (Hi << 8) | Lo`
+pub fn parse_computed(conf_line: &str) -> Result<Option<MaskItem>> {
+    let line = strip_comment(conf_line);
+    let (lhs, rhs) = match line.split_once('=') {
+        // tlv(type=u8,len=u16) and attributes like Volt:f32:unit=V have a = as well
+        Some((lhs, rhs))
+            if !lhs.contains('(') && lhs.matches(':').count() < 2 && !rhs.starts_with('=') =>
+        {
+            (lhs.trim(), rhs)
+        }
+        _ => return Ok(None),
+    };
+    let (name, form) = match lhs.split_once(':') {
+        Some((name, form)) => (name, Format::from_str(form)),
+        None => (lhs, Format::Norm),
+    };
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        bail!(
+            "Syntax error in config, invalid name of computed field \"{}\".",
+            lhs
+        );
+    }
+    let expr = parse_expr(rhs)
+        .with_context(|| format!("Syntax error in config, in expression of {}.", name))?;
+    Ok(Some(MaskItem::Computed(name.to_owned(), expr, form)))
+}
+
+fn field_item(conf_line: &str) -> Result<MaskItem> {
+    let (_, val_type, _, _) = parse_config_line(conf_line)?;
+    if is_tlv_type(val_type) {
+        Ok(MaskItem::Tlv(conf_line.to_owned(), Vec::new()))
+    } else {
+        Ok(MaskItem::Field(conf_line.to_owned()))
+    }
+}
+
+pub fn parse_mask(config_lines: &[String]) -> Result<Vec<MaskItem>> {
+    // the bottom of the stack is the mask itself
+    let mut stack: Vec<(BlockKind, Vec<MaskItem>)> = vec![(BlockKind::Struct, Vec::new())];
+    for conf_line in config_lines.iter() {
+        let line = strip_comment(conf_line);
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(head) = line.strip_suffix('{') {
+            let head = head.trim();
+            let kind = if head.contains(':') {
+                match field_item(head)? {
+                    MaskItem::Tlv(tlv_line, _) => BlockKind::Tlv(tlv_line, Vec::new()),
+                    _ => bail!("Syntax error in config, only tlv fields can have a block."),
+                }
+            } else if let Some(tlv_type) = head.strip_prefix("case ") {
+                if !matches!(stack.last(), Some((BlockKind::Tlv(..), _))) {
+                    bail!("Syntax error in config, case blocks belong into a tlv block.");
+                }
+                BlockKind::Case(
+                    parse_number(tlv_type).context("Syntax error in config, invalid tlv type.")?,
+                )
+            } else if let Some(condition) = head.strip_prefix("if ") {
+                BlockKind::If(
+                    parse_expr(condition)
+                        .context("Syntax error in config, in condition of if block.")?,
+                )
+            } else {
+                match head {
+                    "overlay" | "union" => BlockKind::Overlay,
+                    "struct" => BlockKind::Struct,
+                    "repeat" => BlockKind::Repeat,
+                    _ => bail!("Syntax error in config, unknown block \"{}\".", line),
+                }
+            };
+            stack.push((kind, Vec::new()));
+            continue;
+        }
+        if let Some(tail) = line.strip_prefix('}') {
+            if stack.len() < 2 {
+                bail!("Syntax error in config, found }} without a block to close.");
+            }
+            let (kind, items) = stack.pop().unwrap(); // can't fail, checked the length above
+            let tail = tail.trim();
+            let mut count = None;
+            match (&kind, tail) {
+                (BlockKind::Repeat, "until end") | (BlockKind::Repeat, "until eof") => {}
+                (BlockKind::Repeat, _) if tail.starts_with("times ") => {
+                    count = Some(
+                        parse_expr(&tail["times ".len()..])
+                            .context("Syntax error in config, in count of repeat block.")?,
+                    );
+                }
+                (BlockKind::Repeat, _) => bail!(
+                    "Syntax error in config, a repeat block ends with \"}} until end\" or \"}} times n\"."
+                ),
+                (_, "") => {}
+                (_, _) => bail!("Syntax error in config, unexpected \"{}\" after }}.", tail),
+            }
+            if let Some(Expr::Num(Value::Int(n))) = &count {
+                if n.saturating_mul(repetitions(&items)) > MAX_REPETITIONS {
+                    bail!(
+                        "Syntax error in config, a repeat block repeats its items more than {} times.",
+                        MAX_REPETITIONS
+                    );
+                }
+            }
+            let item = match kind {
+                BlockKind::Overlay => MaskItem::Overlay(items),
+                BlockKind::Struct => MaskItem::Struct(items),
+                BlockKind::Repeat => MaskItem::Repeat(count, items),
+                BlockKind::If(condition) => MaskItem::If(condition, items),
+                BlockKind::Tlv(tlv_line, cases) => MaskItem::Tlv(tlv_line, cases),
+                BlockKind::Case(tlv_type) => {
+                    // checked when the case block was opened that the parent is a tlv
+                    if let Some((BlockKind::Tlv(_, cases), _)) = stack.last_mut() {
+                        cases.push((tlv_type, items));
+                    }
+                    continue;
+                }
+            };
+            stack.last_mut().unwrap().1.push(item);
+            continue;
+        }
+        if let Some((BlockKind::Tlv(..), _)) = stack.last() {
+            bail!("Syntax error in config, a tlv block can only contain case blocks.");
+        }
+        let item = if let Some(computed) = parse_computed(line)? {
+            computed
+        } else if let Some(directive) = parse_directive(line)? {
+            MaskItem::Directive(directive)
+        } else {
+            field_item(conf_line)?
+        };
+        stack.last_mut().unwrap().1.push(item);
+    }
+    if stack.len() > 1 {
+        bail!("Syntax error in config, a block is missing its closing }}.");
+    }
+    Ok(stack.pop().unwrap().1)
+}
+
+// the most times an item of the mask is repeated by repeat blocks with a fixed count
+fn repetitions(items: &[MaskItem]) -> i128 {
+    items
+        .iter()
+        .map(|item| match item {
+            MaskItem::Repeat(Some(Expr::Num(Value::Int(count))), members) => {
+                (*count).max(0).saturating_mul(repetitions(members))
+            }
+            MaskItem::Overlay(members)
+            | MaskItem::Struct(members)
+            | MaskItem::Repeat(_, members)
+            | MaskItem::If(_, members) => repetitions(members),
+            MaskItem::Tlv(_, cases) => cases
+                .iter()
+                .map(|(_, members)| repetitions(members))
+                .max()
+                .unwrap_or(1),
+            MaskItem::Field(_) | MaskItem::Directive(_) | MaskItem::Computed(..) => 1,
+        })
+        .max()
+        .unwrap_or(1)
+}
+
+// true if the mask contains items that consume the chunk until its end,
+// their size depends on the data and not on the config
+pub fn is_open_ended(items: &[MaskItem]) -> bool {
+    items.iter().any(|item| match item {
+        MaskItem::Field(_) | MaskItem::Directive(_) | MaskItem::Computed(..) => false,
+        MaskItem::Overlay(members) | MaskItem::Struct(members) => is_open_ended(members),
+        // a fixed number of repetitions has a fixed size
+        MaskItem::Repeat(Some(Expr::Num(Value::Int(_))), members) => is_open_ended(members),
+        MaskItem::Repeat(..) | MaskItem::If(..) | MaskItem::Tlv(..) => true,
+    })
+}
+
+// round bitpos up to the next multiple of alignment (in bits), counted from mask_start
+pub fn align_bitpos(bitpos: usize, mask_start: usize, alignment: usize) -> usize {
+    if alignment <= 1 || bitpos < mask_start {
+        return bitpos;
+    }
+    let rel = bitpos - mask_start;
+    mask_start + rel.div_ceil(alignment) * alignment
+}
+
+pub fn apply_directive(
+    directive: &Directive,
+    bitpos_in_chunk: &mut usize,
+    mask_start: usize,
+    repr_c: &mut bool,
+) {
+    match directive {
+        Directive::Position(pos) => *bitpos_in_chunk = mask_start + pos,
+        Directive::Align(n) => {
+            *bitpos_in_chunk = align_bitpos(*bitpos_in_chunk, mask_start, n * BYTE_TO_BIT)
+        }
+        Directive::Repr(on) => *repr_c = *on,
+    }
+}
+
+// alignment of a field in bits like a C compiler would lay it out in a repr(C) struct.
+// Types that are not a whole number of bytes are treated like packed bitfields.
+pub fn field_alignment(val_type: &str) -> usize {
+    let bytes = match val_type.to_lowercase().as_str() {
+        "u16" | "i16" => 2,
+        "u32" | "i32" | "f32" => 4,
+        "u64" | "i64" | "f64" => 8,
+        "u128" | "i128" => 16,
+        "bool1" | "iarb" | "uarb" | "bitgap" => return 1,
+        _ => 1,
+    };
+    bytes * BYTE_TO_BIT
+}
+
+// alignment of an item in repr(C) mode, a block is aligned like its strictest member
+pub fn item_alignment(item: &MaskItem) -> Result<usize> {
+    match item {
+        MaskItem::Field(conf_line) => Ok(field_alignment(parse_config_line(conf_line)?.1)),
+        MaskItem::Directive(_) | MaskItem::Tlv(..) | MaskItem::Computed(..) => Ok(1),
+        MaskItem::Overlay(members)
+        | MaskItem::Struct(members)
+        | MaskItem::Repeat(_, members)
+        | MaskItem::If(_, members) => {
+            let mut alignment = 1;
+            for member in members.iter() {
+                alignment = alignment.max(item_alignment(member)?);
+            }
+            Ok(alignment)
+        }
+    }
+}
+
+// walk the mask without any data and return the bit position after it.
+// end is set to the furthest position a field reaches, max_alignment to the
+// strictest alignment that was applied in repr(C) mode.
+pub fn mask_end(
+    items: &[MaskItem],
+    bitpos: &mut usize,
+    mask_start: usize,
+    repr_c: &mut bool,
+    end: &mut usize,
+    max_alignment: &mut usize,
+) -> Result<()> {
+    for item in items.iter() {
+        if *repr_c {
+            let alignment = item_alignment(item)?;
+            *max_alignment = (*max_alignment).max(alignment);
+            *bitpos = align_bitpos(*bitpos, mask_start, alignment);
+        }
+        match item {
+            MaskItem::Field(conf_line) => {
+                let (_, val_type, _, len) = parse_config_line(conf_line)?;
+                match field_size_in_bits(val_type, len) {
+                    Some(size) => *bitpos += size,
+                    None => eprintln!("unknown type"),
+                }
+            }
+            MaskItem::Directive(directive) => {
+                apply_directive(directive, bitpos, mask_start, repr_c)
+            }
+            MaskItem::Overlay(members) => {
+                // only the largest member counts
+                let start = *bitpos;
+                let mut overlay_end = start;
+                for member in members.iter() {
+                    *bitpos = start;
+                    mask_end(
+                        std::slice::from_ref(member),
+                        bitpos,
+                        mask_start,
+                        repr_c,
+                        end,
+                        max_alignment,
+                    )?;
+                    overlay_end = overlay_end.max(*bitpos);
+                }
+                *bitpos = overlay_end;
+            }
+            MaskItem::Struct(members) => {
+                mask_end(members, bitpos, mask_start, repr_c, end, max_alignment)?
+            }
+            MaskItem::Repeat(Some(Expr::Num(Value::Int(count))), members) => {
+                for _ in 0..*count {
+                    mask_end(members, bitpos, mask_start, repr_c, end, max_alignment)?;
+                }
+            }
+            // the size of these depends on the data, they do not count
+            MaskItem::Repeat(..) | MaskItem::If(..) | MaskItem::Tlv(..) => {}
+            MaskItem::Computed(..) => {}
+        }
+        *end = (*end).max(*bitpos);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directive_byte_position() {
+        assert_eq!(
+            parse_directive("@0x40").unwrap(),
+            Some(Directive::Position(0x40 * 8))
+        );
+        assert_eq!(
+            parse_directive("@byte 3 # comment").unwrap(),
+            Some(Directive::Position(24))
+        );
+    }
+    #[test]
+    fn test_parse_directive_bit_position() {
+        assert_eq!(
+            parse_directive("@bit 517").unwrap(),
+            Some(Directive::Position(517))
+        );
+    }
+    #[test]
+    fn test_parse_directive_align() {
+        assert_eq!(
+            parse_directive("align 4").unwrap(),
+            Some(Directive::Align(4))
+        );
+        assert!(parse_directive("align 0").is_err());
+    }
+    #[test]
+    fn test_parse_directive_repr() {
+        assert_eq!(
+            parse_directive("repr(C)").unwrap(),
+            Some(Directive::Repr(true))
+        );
+        assert_eq!(
+            parse_directive("repr C").unwrap(),
+            Some(Directive::Repr(true))
+        );
+        assert_eq!(
+            parse_directive("repr packed").unwrap(),
+            Some(Directive::Repr(false))
+        );
+    }
+    #[test]
+    fn test_parse_directive_field_line() {
+        assert_eq!(parse_directive("align:u8").unwrap(), None);
+        assert_eq!(parse_directive("Field:u16:hex").unwrap(), None);
+    }
+    #[test]
+    fn test_parse_mask_overlay() {
+        let config = "Before:u8
+overlay { # comment
+Raw:u32:hex
+union {
+Float:f32
+}
+
+struct {
+High:u16
+Low:u16
+}
+}
+After:u8";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let items = parse_mask(&config_lines).unwrap();
+        assert_eq!(items.len(), 3);
+        match &items[1] {
+            MaskItem::Overlay(members) => {
+                assert_eq!(members.len(), 3);
+                assert_eq!(members[0], MaskItem::Field("Raw:u32:hex".to_owned()));
+                assert!(matches!(members[1], MaskItem::Overlay(_)));
+                assert!(matches!(members[2], MaskItem::Struct(_)));
+            }
+            _ => panic!("expected an overlay"),
+        }
+    }
+    #[test]
+    fn test_parse_mask_unbalanced() {
+        let open: Vec<String> = vec!["overlay {".to_owned(), "A:u8".to_owned()];
+        assert!(parse_mask(&open).is_err());
+        let close: Vec<String> = vec!["A:u8".to_owned(), "}".to_owned()];
+        assert!(parse_mask(&close).is_err());
+        let unknown: Vec<String> = vec!["dunno {".to_owned(), "}".to_owned()];
+        assert!(parse_mask(&unknown).is_err());
+    }
+    #[test]
+    fn test_parse_mask_repeat() {
+        let config = "Count:u8
+repeat {
+Id:u8
+Value:u16
+} until end";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let items = parse_mask(&config_lines).unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[1], MaskItem::Repeat(None, body) if body.len() == 2));
+        assert!(is_open_ended(&items));
+
+        let times: Vec<String> = vec![
+            "repeat {".to_owned(),
+            "A:u8".to_owned(),
+            "} times 3".to_owned(),
+        ];
+        let items = parse_mask(&times).unwrap();
+        assert!(matches!(&items[0], MaskItem::Repeat(Some(Expr::Num(_)), _)));
+        assert!(!is_open_ended(&items));
+        let mut end = 0;
+        mask_end(&items, &mut 0, 0, &mut false, &mut end, &mut 1).unwrap();
+        assert_eq!(end, 24);
+
+        // nested counts multiply
+        let lines = |config: &str| config.lines().map(|s| s.to_owned()).collect::<Vec<_>>();
+        assert!(parse_mask(&lines("repeat {\nA:u8\n} times 100000000000")).is_err());
+        let nested = "repeat {\nrepeat {\nA:u8\n} times 1000\n} times 1001";
+        assert!(parse_mask(&lines(nested)).is_err());
+        assert!(parse_mask(&lines("repeat {\nA:u8\n} times 1000000")).is_ok());
+
+        let missing_until: Vec<String> = vec!["repeat {".to_owned(), "}".to_owned()];
+        assert!(parse_mask(&missing_until).is_err());
+    }
+    #[test]
+    fn test_parse_mask_if() {
+        let config = "Flags:u8
+if Flags & 1 {
+Extra:u16
+}";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let items = parse_mask(&config_lines).unwrap();
+        assert!(matches!(&items[1], MaskItem::If(_, body) if body.len() == 1));
+        assert!(is_open_ended(&items));
+        let bad: Vec<String> = vec!["if 1 + {".to_owned(), "}".to_owned()];
+        assert!(parse_mask(&bad).is_err());
+    }
+    #[test]
+    fn test_parse_mask_tlv() {
+        let config = "Items:tlv(type=u8,len=u16) {
+case 1 {
+Temperature:i16
+}
+case 0x10 {
+Name:String:4
+Flag:bool8
+}
+}
+Plain:tlv";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let items = parse_mask(&config_lines).unwrap();
+        assert_eq!(items.len(), 2);
+        match &items[0] {
+            MaskItem::Tlv(tlv_line, cases) => {
+                assert_eq!(tlv_line, "Items:tlv(type=u8,len=u16)");
+                assert_eq!(cases.len(), 2);
+                assert_eq!(cases[1].0, 0x10);
+                assert_eq!(cases[1].1.len(), 2);
+            }
+            _ => panic!("expected a tlv"),
+        }
+        assert!(matches!(&items[1], MaskItem::Tlv(_, cases) if cases.is_empty()));
+
+        let field_in_tlv: Vec<String> =
+            vec!["T:tlv {".to_owned(), "A:u8".to_owned(), "}".to_owned()];
+        assert!(parse_mask(&field_in_tlv).is_err());
+        let case_outside: Vec<String> = vec!["case 1 {".to_owned(), "}".to_owned()];
+        assert!(parse_mask(&case_outside).is_err());
+    }
+    #[test]
+    fn test_parse_computed() {
+        let item = parse_computed("Power = Voltage * Current # in W").unwrap();
+        assert!(matches!(item, Some(MaskItem::Computed(name, _, Format::Norm)) if name == "Power"));
+        let item = parse_computed("Id:hex = (Hi << 8) | Lo").unwrap();
+        assert!(matches!(item, Some(MaskItem::Computed(name, _, Format::Hex)) if name == "Id"));
+        assert_eq!(parse_computed("Items:tlv(type=u8,len=u16)").unwrap(), None);
+        assert_eq!(parse_computed("Field:u8").unwrap(), None);
+        assert_eq!(parse_computed("Volt:f32:unit=V").unwrap(), None);
+        assert!(parse_computed("Bad = 1 +").is_err());
+        assert!(parse_computed("My Field = 1").is_err());
+    }
+    #[test]
+    fn test_parse_tlv_type() {
+        assert_eq!(parse_tlv_type("tlv(type=u8,len=u16)").unwrap(), (8, 16));
+        assert_eq!(parse_tlv_type("TLV").unwrap(), (8, 8));
+        assert_eq!(parse_tlv_type("tlv(len=u32)").unwrap(), (8, 32));
+        assert!(parse_tlv_type("tlv(type=f32)").is_err());
+    }
+    #[test]
+    fn test_align_bitpos() {
+        assert_eq!(align_bitpos(0, 0, 32), 0);
+        assert_eq!(align_bitpos(1, 0, 32), 32);
+        assert_eq!(align_bitpos(33, 0, 32), 64);
+        // alignment is counted from the start of the mask, not of the chunk
+        assert_eq!(align_bitpos(20, 4, 16), 20);
+        assert_eq!(align_bitpos(21, 4, 16), 36);
+    }
+}
@@ -0,0 +1,246 @@
+use pcap_parser::Linktype;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Range,
+};
+
+// Decoding of the headers of captured packets, from the link layer down to
+// UDP or TCP, so the mask can be applied to the payload.
+
+const LINKTYPE_LINUX_SLL2: Linktype = Linktype(276);
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const IP_PROTO_TCP: u8 = 6;
+const IP_PROTO_UDP: u8 = 17;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protocol::Udp => write!(f, "UDP"),
+            Protocol::Tcp => write!(f, "TCP"),
+        }
+    }
+}
+
+// the addresses of a packet and where its payload is in the frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub protocol: Protocol,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: Range<usize>,
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} -> {}", self.protocol, self.src, self.dst)
+    }
+}
+
+fn be16(frame: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        frame.get(pos..pos + 2)?.try_into().ok()?,
+    ))
+}
+
+// decodes a frame of a capture down to its UDP or TCP payload, None if it is
+// no UDP or TCP packet over IP
+pub fn decode(linktype: Linktype, frame: &[u8]) -> Option<Packet> {
+    let (ethertype, pos) = match linktype {
+        Linktype::ETHERNET => ethernet(frame, 12)?,
+        // the cooked header of Linux, the protocol is at the end
+        Linktype::LINUX_SLL => ethernet(frame, 14)?,
+        LINKTYPE_LINUX_SLL2 => (be16(frame, 0)?, 20),
+        Linktype::RAW => match frame.first()? >> 4 {
+            4 => (ETHERTYPE_IPV4, 0),
+            6 => (ETHERTYPE_IPV6, 0),
+            _ => return None,
+        },
+        Linktype::IPV4 => (ETHERTYPE_IPV4, 0),
+        Linktype::IPV6 => (ETHERTYPE_IPV6, 0),
+        // loopback, the address family in the byte order of the host or in
+        // network byte order, the families of IPv6 differ between systems
+        Linktype::NULL | Linktype::LOOP => {
+            let family: [u8; 4] = frame.get(..4)?.try_into().ok()?;
+            let family = match linktype {
+                Linktype::LOOP => u32::from_be_bytes(family),
+                _ if family[0] == 0 => u32::from_be_bytes(family),
+                _ => u32::from_le_bytes(family),
+            };
+            match family {
+                2 => (ETHERTYPE_IPV4, 4),
+                24 | 28 | 30 => (ETHERTYPE_IPV6, 4),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    match ethertype {
+        ETHERTYPE_IPV4 => ipv4(frame, pos),
+        ETHERTYPE_IPV6 => ipv6(frame, pos),
+        _ => None,
+    }
+}
+
+// the ethertype at pos and the start of what follows, behind VLAN tags
+fn ethernet(frame: &[u8], mut pos: usize) -> Option<(u16, usize)> {
+    let mut ethertype = be16(frame, pos)?;
+    while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+        pos += 4;
+        ethertype = be16(frame, pos)?;
+    }
+    Some((ethertype, pos + 2))
+}
+
+fn ipv4(frame: &[u8], pos: usize) -> Option<Packet> {
+    let header = frame.get(pos..pos + 20)?;
+    let header_len = (header[0] & 0x0F) as usize * 4;
+    if header_len < 20 {
+        return None;
+    }
+    // padding of the link layer is behind the total length, a total length
+    // of 0 is left to the NIC by segmentation offload on the sending host
+    let end = match be16(header, 2)? {
+        0 => frame.len(),
+        total_len => (pos + total_len as usize).min(frame.len()),
+    };
+    // only the first fragment has the header of UDP or TCP
+    if be16(header, 6)? & 0x1FFF != 0 {
+        return None;
+    }
+    let src = IpAddr::V4(Ipv4Addr::new(
+        header[12], header[13], header[14], header[15],
+    ));
+    let dst = IpAddr::V4(Ipv4Addr::new(
+        header[16], header[17], header[18], header[19],
+    ));
+    transport(frame, header[9], pos + header_len, end, src, dst)
+}
+
+fn ipv6(frame: &[u8], pos: usize) -> Option<Packet> {
+    let header = frame.get(pos..pos + 40)?;
+    let end = (pos + 40 + be16(header, 4)? as usize).min(frame.len());
+    let src: [u8; 16] = header[8..24].try_into().ok()?;
+    let dst: [u8; 16] = header[24..40].try_into().ok()?;
+    // skip extension headers: hop-by-hop, routing, fragment and destination options
+    let mut next = header[6];
+    let mut pos = pos + 40;
+    loop {
+        match next {
+            0 | 43 | 60 => {
+                next = *frame.get(pos)?;
+                pos += (*frame.get(pos + 1)? as usize + 1) * 8;
+            }
+            44 => {
+                // only the first fragment has the header of UDP or TCP
+                if be16(frame, pos + 2)? & 0xFFF8 != 0 {
+                    return None;
+                }
+                next = *frame.get(pos)?;
+                pos += 8;
+            }
+            _ => break,
+        }
+    }
+    let src = IpAddr::V6(Ipv6Addr::from(src));
+    let dst = IpAddr::V6(Ipv6Addr::from(dst));
+    transport(frame, next, pos, end, src, dst)
+}
+
+fn transport(
+    frame: &[u8],
+    proto: u8,
+    pos: usize,
+    end: usize,
+    src: IpAddr,
+    dst: IpAddr,
+) -> Option<Packet> {
+    let (protocol, header_len) = match proto {
+        IP_PROTO_UDP => (Protocol::Udp, 8),
+        IP_PROTO_TCP => (Protocol::Tcp, (*frame.get(pos + 12)? >> 4) as usize * 4),
+        _ => return None,
+    };
+    let start = pos + header_len;
+    if start > end {
+        return None;
+    }
+    Some(Packet {
+        protocol,
+        src: SocketAddr::new(src, be16(frame, pos)?),
+        dst: SocketAddr::new(dst, be16(frame, pos + 2)?),
+        payload: start..end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an IPv4 packet with UDP from 10.0.0.5:5000 to 10.0.0.7:17224
+    fn ipv4_udp(payload: &[u8]) -> Vec<u8> {
+        let total = (20 + 8 + payload.len()) as u16;
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, IP_PROTO_UDP, 0, 0];
+        packet[2..4].copy_from_slice(&total.to_be_bytes());
+        packet.extend([10, 0, 0, 5, 10, 0, 0, 7]);
+        packet.extend(5000u16.to_be_bytes());
+        packet.extend(17224u16.to_be_bytes());
+        packet.extend((8 + payload.len() as u16).to_be_bytes());
+        packet.extend([0, 0]);
+        packet.extend(payload);
+        packet
+    }
+
+    #[test]
+    fn test_decode() {
+        // Ethernet with a VLAN tag and padding behind the packet
+        let mut frame = vec![0; 12];
+        frame.extend([0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        frame.extend(ipv4_udp(b"abc"));
+        frame.extend([0; 6]);
+        let packet = decode(Linktype::ETHERNET, &frame).unwrap();
+        assert_eq!(packet.to_string(), "UDP 10.0.0.5:5000 -> 10.0.0.7:17224");
+        assert_eq!(&frame[packet.payload], b"abc");
+        // loopback of BSD, the family in the byte order of the host
+        let mut frame = vec![2, 0, 0, 0];
+        frame.extend(ipv4_udp(b"xy"));
+        let packet = decode(Linktype::NULL, &frame).unwrap();
+        assert_eq!(&frame[packet.payload], b"xy");
+        // IPv6 with TCP, the header of TCP with 4 bytes of options
+        let mut frame = vec![0x60, 0, 0, 0, 0, 26, IP_PROTO_TCP, 64];
+        frame.extend(Ipv6Addr::LOCALHOST.octets());
+        frame.extend(Ipv6Addr::LOCALHOST.octets());
+        frame.extend(80u16.to_be_bytes());
+        frame.extend(40000u16.to_be_bytes());
+        frame.extend([0; 8]);
+        frame.extend([0x60, 0x18, 0, 0, 0, 0, 0, 0]);
+        frame.extend([1, 1, 1, 1]);
+        frame.extend(b"hi");
+        let packet = decode(Linktype::RAW, &frame).unwrap();
+        assert_eq!(packet.to_string(), "TCP [::1]:80 -> [::1]:40000");
+        assert_eq!(&frame[packet.payload], b"hi");
+        // the total length of an offloaded segment is 0
+        let mut frame = ipv4_udp(b"tso");
+        frame[2..4].copy_from_slice(&[0, 0]);
+        let packet = decode(Linktype::IPV4, &frame).unwrap();
+        assert_eq!(&frame[packet.payload], b"tso");
+        // a header shorter than 20 bytes is no IPv4
+        frame[0] = 0x44;
+        assert!(decode(Linktype::IPV4, &frame).is_none());
+        // ARP is no IP
+        let mut frame = vec![0; 12];
+        frame.extend([0x08, 0x06]);
+        frame.extend([0; 28]);
+        assert!(decode(Linktype::ETHERNET, &frame).is_none());
+    }
+}
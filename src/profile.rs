@@ -0,0 +1,161 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+// Named profiles in ~/.config/mview/config.toml. A profile is a set of
+// options, like they were given on the command line:
+//
+// [profiles.trdp]
+// config = "~/masks/trdp.conf"
+// pcap = true
+// offset = 42
+// little_endian = true
+// rawhex = true
+// args = ["--stats"]
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UserConfig {
+    profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Profile {
+    config: Option<String>,
+    select: Option<String>,
+    pcap: Option<bool>,
+    chunksize: Option<usize>,
+    offset: Option<usize>,
+    bitoffset: Option<usize>,
+    little_endian: Option<bool>,
+    rawhex: Option<bool>,
+    rawbin: Option<bool>,
+    rawascii: Option<bool>,
+    timestamp: Option<bool>,
+    stats: Option<bool>,
+    nojump: Option<bool>,
+    clear: Option<bool>,
+    args: Vec<String>, // any other options
+}
+
+// $XDG_CONFIG_HOME/mview/config.toml or ~/.config/mview/config.toml
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("mview").join("config.toml"))
+}
+
+// a path starting with ~/ is in the home directory
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ => path.to_owned(),
+    }
+}
+
+// the options of a profile of the user config
+pub fn profile_options(user_config: &str, name: &str) -> Result<Vec<String>> {
+    let user_config: UserConfig = toml::from_str(user_config).context("Invalid user config")?;
+    let Some(profile) = user_config.profiles.get(name) else {
+        let names: Vec<&str> = user_config.profiles.keys().map(|n| n.as_str()).collect();
+        bail!(
+            "No profile {} in the user config, there are: {}",
+            name,
+            names.join(", ")
+        );
+    };
+    let mut options = Vec::new();
+    let mut value = |option: &str, value: Option<String>| {
+        if let Some(value) = value {
+            options.push(option.to_owned());
+            options.push(value);
+        }
+    };
+    value("--config", profile.config.as_deref().map(expand_home));
+    value("--struct", profile.select.clone());
+    value("--chunksize", profile.chunksize.map(|n| n.to_string()));
+    value("--offset", profile.offset.map(|n| n.to_string()));
+    value("--bitoffset", profile.bitoffset.map(|n| n.to_string()));
+    // false switches a flag of the config off
+    for (flag, negation, on) in [
+        ("--pcap", "--no-pcap", profile.pcap),
+        (
+            "--little-endian",
+            "--no-little-endian",
+            profile.little_endian,
+        ),
+        ("--rawhex", "--no-rawhex", profile.rawhex),
+        ("--rawbin", "--no-rawbin", profile.rawbin),
+        ("--rawascii", "--no-rawascii", profile.rawascii),
+        ("--timestamp", "--no-timestamp", profile.timestamp),
+        ("--stats", "--no-stats", profile.stats),
+        ("--nojump", "--jump", profile.nojump),
+        ("--clear", "--no-clear", profile.clear),
+    ] {
+        match on {
+            Some(true) => options.push(flag.to_owned()),
+            Some(false) => options.push(negation.to_owned()),
+            None => {}
+        }
+    }
+    options.extend(profile.args.iter().cloned());
+    Ok(options)
+}
+
+// the options of a profile of the user config file
+pub fn read_profile(name: &str) -> Result<Vec<String>> {
+    let path = user_config_path().context("Could not find the home directory")?;
+    let user_config = fs::read_to_string(&path)
+        .with_context(|| format!("Could not read user config {}", path.display()))?;
+    profile_options(&user_config, name)
+        .with_context(|| format!("Could not use profile {} of {}", name, path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_options() {
+        let user_config = r#"
+[profiles.trdp]
+config = "/masks/trdp.conf"
+pcap = true
+offset = 42
+little_endian = true
+args = ["--stats"]
+
+[profiles.can]
+config = "/masks/can.dbc"
+select = "EngineData"
+pcap = false
+"#;
+        assert_eq!(
+            profile_options(user_config, "trdp").unwrap(),
+            vec![
+                "--config",
+                "/masks/trdp.conf",
+                "--offset",
+                "42",
+                "--pcap",
+                "--little-endian",
+                "--stats"
+            ]
+        );
+        assert_eq!(
+            profile_options(user_config, "can").unwrap(),
+            vec![
+                "--config",
+                "/masks/can.dbc",
+                "--struct",
+                "EngineData",
+                "--no-pcap"
+            ]
+        );
+        assert!(profile_options(user_config, "udp").is_err());
+        assert!(profile_options("[profiles.x]\nendian = 1", "x").is_err());
+    }
+}
@@ -1,103 +1,875 @@
-use crate::{args::Args, PcapTs, MAX_READ_SIZE};
-use anyhow::{Context, Result};
-use crossbeam::channel::Sender;
-use pcap_parser::traits::PcapReaderIterator;
+use crate::{
+    args::Args,
+    chunksize_by_config,
+    mask::is_open_ended,
+    mask::parse_mask,
+    net::{self, Packet, Protocol},
+    read_config, Message, PcapTs, BYTE_TO_BIT, MAX_READ_SIZE,
+};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use crossbeam::channel::{Sender, TrySendError};
+use crossterm::style::{self, Color, Stylize};
+use pcap_parser::traits::{PcapNGPacketBlock, PcapReaderIterator};
 use pcap_parser::*;
-use std::sync::{Arc, Mutex};
 use std::{
     fs::File,
     io::{self, BufReader, Read},
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    thread,
+    time::Duration,
 };
 
 const PCAP_MAGIC_US: u32 = 0xA1B2C3D4;
 const PCAP_MAGIC_US_BE: u32 = 0xD4C3B2A1;
 const PCAP_MAGIC_NS: u32 = 0xA1B23C4D;
 const PCAP_MAGIC_NS_BE: u32 = 0x4D3CB2A1;
+const PCAPNG_DEFAULT_RESOLUTION: u64 = 1_000_000; // without if_tsresol timestamps are in µs
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+const PCAPNG_EPB_LEN: usize = 32; // an enhanced packet block without data and options
+const MAX_SNAPLEN: u32 = 262_144; // of tcpdump and Wireshark, larger records are corrupt
+const MAX_RESYNC_GAP: u32 = 86_400; // seconds from the last record to one found by resyncing
+const FOLLOW_MIN_WAIT: Duration = Duration::from_millis(10);
+const FOLLOW_MAX_WAIT: Duration = Duration::from_millis(500);
+const MAX_DATAGRAM_SIZE: usize = 65_536;
+const UDP_IDLE_CHECK: Duration = Duration::from_millis(250);
 
-pub fn read_loop(
-    args: &Args,
-    write_tx: Sender<Vec<u8>>,
-    pcap_ts: Arc<Mutex<PcapTs>>,
-) -> Result<()> {
-    if args.pcap {
-        let reader: Box<dyn Read> = if !args.infile.is_empty() {
-            Box::new(BufReader::new(File::open(&args.infile)?))
-        } else {
-            Box::new(BufReader::new(io::stdin()))
+// how damaged the input was, it tells the exit code
+#[derive(Debug, Default, PartialEq)]
+pub struct ReadReport {
+    pub skipped_bytes: usize, // of corrupt data and a truncated last record
+    pub corrupt: usize,       // places in the input with corrupt data
+    pub truncated: bool,      // the input ends inside a record
+    pub oversized: usize,     // records larger than --pcap-buffer
+}
+
+impl ReadReport {
+    pub fn is_damaged(&self) -> bool {
+        self.corrupt > 0 || self.truncated || self.oversized > 0
+    }
+}
+
+// skips corrupt data of pcap input byte by byte until a record starts again
+#[derive(Default)]
+struct Resync {
+    start: Option<usize>, // where the corrupt data starts in the input
+    last_ts: Option<u32>, // seconds of the last record of a legacy pcap file
+}
+
+impl Resync {
+    fn skip(&mut self, reader: &mut dyn PcapReaderIterator) {
+        if self.start.is_none() {
+            let start = reader.consumed();
+            eprintln!(
+                "{}: Corrupt record at byte {} of the input, looking for the next one.",
+                style::style("WARNING").with(Color::Yellow).bold(),
+                start
+            );
+            self.start = Some(start);
+        }
+        reader.consume(1);
+    }
+    // the corrupt data ends at position
+    fn found(&mut self, position: usize, report: &mut ReadReport) {
+        if let Some(start) = self.start.take() {
+            eprintln!(
+                "Skipped {} bytes of corrupt data up to byte {}.",
+                position - start,
+                position
+            );
+            report.corrupt += 1;
+            report.skipped_bytes += position - start;
+        }
+    }
+}
+
+// grows the buffer of the reader to hold size bytes, but not beyond the limit
+// of --pcap-buffer. Returns false if it can't grow.
+fn grow_buffer(
+    reader: &mut dyn PcapReaderIterator,
+    capacity: &mut usize,
+    size: usize,
+    limit: usize,
+) -> bool {
+    let size = size.min(limit);
+    if size <= *capacity {
+        return false;
+    }
+    reader.grow(size);
+    *capacity = size;
+    true
+}
+
+// the length of the record at the start of data, told by its header
+fn record_len(
+    legacy_header: Option<&PcapHeader>,
+    ng_big_endian: bool,
+    data: &[u8],
+) -> Option<usize> {
+    // caplen of a legacy record, the total length of a pcapng block
+    let (pos, big_endian, header_len) = match legacy_header {
+        Some(header) => (8, header.is_bigendian(), PCAP_RECORD_HEADER_LEN),
+        None => (4, ng_big_endian, 0),
+    };
+    let bytes = data.get(pos..pos + 4)?.try_into().ok()?;
+    let len = match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    };
+    Some(header_len + len as usize)
+}
+
+// skips len bytes of the input, more than the buffer holds, and returns how
+// many were skipped, fewer if the input ends before
+fn skip_input(reader: &mut dyn PcapReaderIterator, len: usize) -> Result<usize> {
+    let mut skipped = 0;
+    loop {
+        let part = reader.data().len().min(len - skipped);
+        reader.consume(part);
+        skipped += part;
+        if skipped == len || reader.reader_exhausted() {
+            return Ok(skipped);
+        }
+        if reader.refill().is_err() {
+            bail!("Could not read the input at byte {}.", reader.consumed());
+        }
+    }
+}
+
+// false if the bytes at the start of data can't be the header of a record of
+// a legacy pcap file, too few bytes to tell are no proof. The pcap reader
+// doesn't check the records, with a corrupt length it would wait for data that
+// never comes. When resyncing the timestamp has to be near the one of the last
+// record and the record behind has to fit as well.
+fn plausible_record(header: &PcapHeader, data: &[u8], resync: Option<&Resync>) -> bool {
+    let Some(record) = data.get(..PCAP_RECORD_HEADER_LEN) else {
+        return true;
+    };
+    let field = |pos: usize| {
+        let bytes = record[pos..pos + 4].try_into().unwrap_or_default();
+        match header.is_bigendian() {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    };
+    let max_fraction = match header.is_nanosecond_precision() {
+        true => 1_000_000_000,
+        false => 1_000_000,
+    };
+    let caplen = field(8);
+    if field(4) >= max_fraction || caplen > header.snaplen.max(MAX_SNAPLEN) || caplen > field(12) {
+        return false;
+    }
+    let Some(resync) = resync else {
+        return true;
+    };
+    let next = data
+        .get(PCAP_RECORD_HEADER_LEN + caplen as usize..)
+        .unwrap_or_default();
+    resync
+        .last_ts
+        .is_none_or(|ts| field(0).abs_diff(ts) <= MAX_RESYNC_GAP)
+        && plausible_record(header, next, None)
+}
+
+// an interface of a pcapng section, packets refer to it by its index
+struct Interface {
+    resolution: u64, // timestamp units per second
+    offset: u64,     // seconds added to the timestamps
+    linktype: Linktype,
+}
+
+// the comments in the options of a pcapng block
+fn comments(options: &[PcapNGOption]) -> Vec<String> {
+    options
+        .iter()
+        .filter(|option| option.code == OptionCode::Comment)
+        .map(|option| {
+            String::from_utf8_lossy(option.value)
+                .trim_end_matches('\0')
+                .to_owned()
+        })
+        .collect()
+}
+
+// a packet of a capture as message, the fraction of the timestamp in ns
+fn captured(ts_s: u32, ts_ns: u32, origlen: u32, data: &[u8], linktype: Linktype) -> Message {
+    Message {
+        payload: data.to_vec(),
+        timestamp: NaiveDateTime::from_timestamp_opt(ts_s.into(), ts_ns)
+            .map(|timestamp| DateTime::<Utc>::from_utc(timestamp, Utc)),
+        caplen: data.len() as u32,
+        origlen,
+        linktype: Some(linktype),
+        packet: None,
+    }
+}
+
+// handles a block of a pcapng file, packets are returned as message with the
+// link type of their interface
+fn ng_block(block: &Block, interfaces: &mut Vec<Interface>) -> Option<Message> {
+    match block {
+        Block::SectionHeader(shb) => {
+            // interfaces are numbered per section
+            interfaces.clear();
+            for comment in comments(&shb.options) {
+                eprintln!("Capture comment: {}", comment);
+            }
+            None
+        }
+        Block::InterfaceDescription(idb) => {
+            for comment in comments(&idb.options) {
+                eprintln!("Interface {} comment: {}", interfaces.len(), comment);
+            }
+            interfaces.push(Interface {
+                resolution: idb.ts_resolution().unwrap_or(PCAPNG_DEFAULT_RESOLUTION),
+                offset: idb.ts_offset(),
+                linktype: idb.linktype,
+            });
+            None
+        }
+        Block::EnhancedPacket(epb) => {
+            let (resolution, offset, linktype) = match interfaces.get(epb.if_id as usize) {
+                Some(interface) => (interface.resolution, interface.offset, interface.linktype),
+                None => (PCAPNG_DEFAULT_RESOLUTION, 0, Linktype::ETHERNET),
+            };
+            let (ts_s, fraction) = epb.decode_ts(offset, resolution);
+            let ts_ns = (fraction as u128 * 1_000_000_000 / resolution as u128) as u32;
+            for comment in comments(&epb.options) {
+                eprintln!("Packet comment: {}", comment);
+            }
+            let data = epb.packet_data();
+            Some(captured(ts_s, ts_ns, epb.orig_len(), data, linktype))
+        }
+        // simple packets have no timestamp and are from the first interface
+        Block::SimplePacket(spb) => {
+            let linktype = interfaces
+                .first()
+                .map_or(Linktype::ETHERNET, |i| i.linktype);
+            let mut message = captured(0, 0, spb.orig_len(), spb.packet_data(), linktype);
+            message.timestamp = None;
+            Some(message)
+        }
+        _ => None,
+    }
+}
+
+// sends a packet of a pcap file to the write thread, with --strip-headers only
+// the UDP or TCP payload of it and with --filter only if it matches.
+// Returns false if the write thread is gone.
+fn send_packet(args: &Args, write_tx: &Sender<Message>, mut message: Message) -> bool {
+    if args.strip_headers || args.print_addresses || args.filter.is_some() {
+        // datagrams of --udp come without headers, their packet is known
+        if let Some(linktype) = message.linktype {
+            message.packet = net::decode(linktype, &message.payload);
+        }
+    }
+    if let Some(filter) = &args.filter {
+        if !message
+            .packet
+            .as_ref()
+            .is_some_and(|packet| filter.matches(packet))
+        {
+            return true;
+        }
+    }
+    if args.strip_headers {
+        let Some(packet) = &message.packet else {
+            // no UDP or TCP packet
+            return true;
         };
-        let mut pcapreader =
-            LegacyPcapReader::new(MAX_READ_SIZE, reader).context("Error creating PCAP reader.")?;
-        loop {
-            // read input
-            match pcapreader.next() {
-                Ok((offset, block)) => {
-                    match block {
-                        PcapBlockOwned::Legacy(ablock) => {
-                            if write_tx.send(ablock.to_vec_raw()?).is_err() {
-                                break;
-                            }
+        let payload = packet.payload.clone();
+        message.payload.truncate(payload.end);
+        message.payload.drain(..payload.start);
+    }
+    write_tx.send(message).is_ok()
+}
+
+// the size of the records of --stream in bytes, like the chunksize of the
+// write thread
+fn record_size(args: &Args) -> Result<usize> {
+    if args.chunksize > 0 {
+        return Ok(args.chunksize);
+    }
+    let config_lines = read_config(&args.config, &args.mask_lines, args)?;
+    if is_open_ended(&parse_mask(&config_lines)?) {
+        bail!("--stream needs --chunksize, the size of the config depends on the data.");
+    }
+    match chunksize_by_config(&config_lines)? / BYTE_TO_BIT {
+        0 => bail!("--stream needs --chunksize, the config is smaller than a byte."),
+        size => Ok(size),
+    }
+}
+
+// reads a capture until its end and sends its packets to the write thread.
+// Returns false if the write thread is gone.
+fn read_pcap(
+    args: &Args,
+    reader: Box<dyn Read>,
+    write_tx: &Sender<Message>,
+    report: &mut ReadReport,
+) -> Result<bool> {
+    // pcap or pcapng, told by the magic at the start
+    let mut pcapreader =
+        create_reader(MAX_READ_SIZE, reader).context("Error creating PCAP reader.")?;
+    let mut capacity = MAX_READ_SIZE;
+    let mut interfaces = Vec::new();
+    // of a legacy pcap file
+    let mut linktype = Linktype::ETHERNET;
+    let mut pcap_ts = PcapTs::Microsecs;
+    let mut legacy_header = None;
+    // of the current section of a pcapng file
+    let mut ng_big_endian = false;
+    let mut resync = Resync::default();
+    loop {
+        if let Some(header) = &legacy_header {
+            let resyncing = resync.start.is_some().then_some(&resync);
+            if !plausible_record(header, pcapreader.data(), resyncing) {
+                resync.skip(&mut *pcapreader);
+                continue;
+            }
+        }
+        let position = pcapreader.consumed();
+        // read input
+        match pcapreader.next() {
+            Ok((offset, block)) => {
+                if resync.start.is_some() && matches!(block, PcapBlockOwned::NG(Block::Unknown(_)))
+                {
+                    // corrupt data can look like a block of an unknown type
+                    resync.skip(&mut *pcapreader);
+                    continue;
+                }
+                resync.found(position, report);
+                match block {
+                    PcapBlockOwned::Legacy(ablock) => {
+                        resync.last_ts = Some(ablock.ts_sec);
+                        // the reader decoded the header in the byte order of the file
+                        let ts_ns = match pcap_ts {
+                            PcapTs::Microsecs => ablock.ts_usec.saturating_mul(1_000),
+                            PcapTs::Nanosecs => ablock.ts_usec,
+                        };
+                        let message =
+                            captured(ablock.ts_sec, ts_ns, ablock.origlen, ablock.data, linktype);
+                        if !send_packet(args, write_tx, message) {
+                            return Ok(false);
                         }
-                        PcapBlockOwned::LegacyHeader(fileheader) => match fileheader.magic_number {
-                            PCAP_MAGIC_US | PCAP_MAGIC_US_BE => {
-                                let mut pcap_ts_format = pcap_ts.lock().unwrap(); // we can use unwrap here,
-                                                                                  // if the mutex can't not be aquired using lock() something went really wrong
-                                *pcap_ts_format = PcapTs::Microsecs;
+                    }
+                    PcapBlockOwned::LegacyHeader(fileheader) => {
+                        linktype = fileheader.network;
+                        match fileheader.magic_number {
+                            PCAP_MAGIC_US | PCAP_MAGIC_US_BE => pcap_ts = PcapTs::Microsecs,
+                            PCAP_MAGIC_NS | PCAP_MAGIC_NS_BE => pcap_ts = PcapTs::Nanosecs,
+                            _ => {}
+                        }
+                        // records are up to the snaplen
+                        let size = PCAP_RECORD_HEADER_LEN + fileheader.snaplen as usize;
+                        grow_buffer(&mut *pcapreader, &mut capacity, size, args.pcap_buffer);
+                        legacy_header = Some(fileheader);
+                    }
+                    PcapBlockOwned::NG(block) => {
+                        // packets of an interface are up to its snaplen
+                        let snaplen = match &block {
+                            Block::InterfaceDescription(idb) => idb.snaplen as usize,
+                            Block::SectionHeader(shb) => {
+                                ng_big_endian = shb.big_endian();
+                                0
                             }
-                            PCAP_MAGIC_NS | PCAP_MAGIC_NS_BE => {
-                                let mut pcap_ts_format = pcap_ts.lock().unwrap();
-                                *pcap_ts_format = PcapTs::Nanosecs;
+                            _ => 0,
+                        };
+                        if let Some(message) = ng_block(&block, &mut interfaces) {
+                            if !send_packet(args, write_tx, message) {
+                                return Ok(false);
                             }
-                            _ => {}
-                        },
-                        PcapBlockOwned::NG(_) => {}
+                        }
+                        let size = PCAPNG_EPB_LEN + snaplen;
+                        grow_buffer(&mut *pcapreader, &mut capacity, size, args.pcap_buffer);
                     }
-                    pcapreader.consume(offset);
                 }
-                Err(PcapError::Eof) => {
-                    if args.infile.is_empty() {
+                pcapreader.consume(offset);
+            }
+            Err(PcapError::Eof) => {
+                resync.found(position, report);
+                return Ok(true);
+            }
+            Err(PcapError::Incomplete) => {
+                let available = pcapreader.data().len();
+                if pcapreader.refill().is_err() {
+                    bail!("Could not read the input at byte {}.", position + available);
+                }
+                // without new data at the end of the input, next() tells Eof
+                if pcapreader.data().len() == available && available > 0 {
+                    // corrupt data can claim to be longer than what is left
+                    if pcapreader.reader_exhausted() && resync.start.is_none() {
+                        eprintln!(
+                            "{}: The input ends inside the record at byte {}.",
+                            style::style("WARNING").with(Color::Yellow).bold(),
+                            position
+                        );
+                        report.skipped_bytes += available;
+                        report.truncated = true;
+                        return Ok(true);
+                    }
+                    // a record larger than the buffer, like one of an
+                    // interface without snaplen
+                    let size = capacity * 2;
+                    if !pcapreader.reader_exhausted()
+                        && grow_buffer(&mut *pcapreader, &mut capacity, size, args.pcap_buffer)
+                    {
                         continue;
-                    } else {
-                        break;
                     }
+                    // a record larger than --pcap-buffer is skipped as a whole
+                    let len = record_len(legacy_header.as_ref(), ng_big_endian, pcapreader.data());
+                    if let Some(len) = len.filter(|&len| len > capacity && resync.start.is_none()) {
+                        eprintln!(
+                            "{}: Record of {} bytes at byte {} exceeds --pcap-buffer, skipping it.",
+                            style::style("WARNING").with(Color::Yellow).bold(),
+                            len,
+                            position
+                        );
+                        let skipped = skip_input(&mut *pcapreader, len)?;
+                        report.oversized += 1;
+                        report.skipped_bytes += skipped;
+                        report.truncated |= skipped < len;
+                        continue;
+                    }
+                    // or the record can't be right
+                    resync.skip(&mut *pcapreader);
                 }
-                Err(PcapError::Incomplete) => {
-                    pcapreader.refill().unwrap();
-                }
-                Err(e) => panic!("error while reading: {:?}", e),
             }
+            Err(PcapError::NomError(..) | PcapError::OwnedNomError(..)) => {
+                resync.skip(&mut *pcapreader);
+            }
+            Err(e) => bail!("Could not read the input at byte {}: {}", position, e),
         }
-    } else {
-        let mut reader: Box<dyn Read> = if !args.infile.is_empty() {
-            Box::new(BufReader::new(File::open(&args.infile)?))
-        } else {
-            Box::new(BufReader::new(io::stdin()))
+    }
+}
+
+// reads raw input until its end and sends what is read to the write thread.
+// Returns false if the write thread is gone or --head is done.
+fn read_raw(args: &Args, mut reader: Box<dyn Read>, write_tx: &Sender<Message>) -> Result<bool> {
+    let mut buffer = [0; MAX_READ_SIZE];
+    let record_size = match args.stream {
+        true => record_size(args)?,
+        false => 0,
+    };
+    // with --stream the bytes of a record that is not complete yet
+    let mut pending = Vec::new();
+    loop {
+        let num_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(x) => x,
+            Err(_) => return Ok(false),
         };
-        let mut buffer = [0; MAX_READ_SIZE];
+        if args.read_head > 0 {
+            let _ = write_tx.send(Message::received(Vec::from(&buffer[..args.read_head])));
+            return Ok(false);
+        } else if args.stream {
+            pending.extend_from_slice(&buffer[..num_read]);
+            let complete = pending.len() / record_size * record_size;
+            let sent = pending[..complete]
+                .chunks(record_size)
+                .all(|record| write_tx.send(Message::received(record.to_vec())).is_ok());
+            pending.drain(..complete);
+            if !sent {
+                return Ok(false);
+            }
+        } else {
+            if write_tx
+                .send(Message::received(Vec::from(&buffer[..num_read])))
+                .is_err()
+            {
+                return Ok(false);
+            }
+        }
+    }
+    // the last record is shorter
+    if !pending.is_empty() {
+        let _ = write_tx.send(Message::received(pending));
+    }
+    Ok(true)
+}
+
+// reads a file that is still written to like tail -f: at its end it waits for
+// more data, polling less often the longer nothing comes. It only ends when
+// the file is truncated or replaced, like by a rotation of logs.
+struct Follow {
+    file: File,
+    path: String,
+    position: u64,
+}
+
+impl Follow {
+    fn open(path: &str) -> io::Result<Self> {
+        Ok(Follow {
+            file: File::open(path)?,
+            path: path.to_owned(),
+            position: 0,
+        })
+    }
+    fn truncated(&self) -> bool {
+        self.file
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() < self.position)
+    }
+    // another file is at the path now
+    #[cfg(unix)]
+    fn replaced(&self) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (self.file.metadata(), std::fs::metadata(&self.path)) {
+            (Ok(open), Ok(now)) => open.ino() != now.ino() || open.dev() != now.dev(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    fn replaced(&self) -> bool {
+        false
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut wait = FOLLOW_MIN_WAIT;
         loop {
-            let num_read = match reader.read(&mut buffer) {
-                Ok(0) => {
-                    if args.infile.is_empty() {
-                        continue;
-                    } else {
-                        break;
-                    }
-                }
-                Ok(x) => x,
-                Err(_) => break,
-            };
-            if args.read_head > 0 {
-                if write_tx.send(Vec::from(&buffer[..args.read_head])).is_err() {
-                    break;
-                }
-                break;
-            } else {
-                if write_tx.send(Vec::from(&buffer[..num_read])).is_err() {
-                    break;
+            let n = self.file.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.position += n as u64;
+                return Ok(n);
+            }
+            if self.truncated() || self.replaced() {
+                return Ok(0);
+            }
+            thread::sleep(wait);
+            wait = (wait * 2).min(FOLLOW_MAX_WAIT);
+        }
+    }
+}
+
+fn open_input(args: &Args) -> Result<Box<dyn Read>> {
+    Ok(if args.infile.is_empty() {
+        Box::new(BufReader::new(io::stdin()))
+    } else if args.follow {
+        Box::new(BufReader::new(Follow::open(&args.infile)?))
+    } else {
+        Box::new(BufReader::new(File::open(&args.infile)?))
+    })
+}
+
+// receives datagrams on an address, each is a message with the address it
+// came from, until the write thread ends. While no datagram comes it checks
+// now and then that the write thread is still there.
+fn read_udp(args: &Args, address: SocketAddr, write_tx: &Sender<Message>) -> Result<()> {
+    let socket = UdpSocket::bind(address)
+        .with_context(|| format!("Could not listen for UDP on {}", address))?;
+    for group in &args.join {
+        match group {
+            IpAddr::V4(group) if group.is_multicast() => {
+                socket.join_multicast_v4(group, &Ipv4Addr::UNSPECIFIED)
+            }
+            IpAddr::V6(group) if group.is_multicast() => socket.join_multicast_v6(group, 0),
+            _ => bail!("{} is no multicast group.", group),
+        }
+        .with_context(|| format!("Could not join multicast group {}", group))?;
+    }
+    let local = socket.local_addr()?;
+    socket.set_read_timeout(Some(UDP_IDLE_CHECK))?;
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, src) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            // nothing came, an empty message tells if the write thread is still there
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if let Err(TrySendError::Disconnected(_)) = write_tx.try_send(Message::default()) {
+                    return Ok(());
                 }
+                continue;
             }
+            Err(e) => return Err(e).context("Could not receive UDP datagram"),
+        };
+        let mut message = Message::received(buffer[..len].to_vec());
+        message.packet = Some(Packet {
+            protocol: Protocol::Udp,
+            src,
+            dst: local,
+            payload: 0..len,
+        });
+        if !send_packet(args, write_tx, message) {
+            return Ok(());
         }
     }
+}
 
-    let _ = write_tx.send(Vec::new());
-    Ok(())
+pub fn read_loop(args: &Args, write_tx: Sender<Message>) -> Result<ReadReport> {
+    let mut report = ReadReport::default();
+    if let Some(address) = args.udp {
+        read_udp(args, address, &write_tx)?;
+        return Ok(report);
+    }
+    loop {
+        let reader = open_input(args)?;
+        let more = match args.pcap {
+            true => read_pcap(args, reader, &write_tx, &mut report)?,
+            false => read_raw(args, reader, &write_tx)?,
+        };
+        // with --follow the input ends where the file was truncated or replaced
+        if !args.follow || !more {
+            break;
+        }
+        eprintln!(
+            "{} was truncated or replaced, reading it from the start.",
+            args.infile
+        );
+    }
+    if report.is_damaged() {
+        let mut places = Vec::new();
+        if report.corrupt > 0 {
+            places.push(match report.corrupt {
+                1 => "1 place with corrupt data".to_owned(),
+                n => format!("{} places with corrupt data", n),
+            });
+        }
+        if report.oversized > 0 {
+            places.push(match report.oversized {
+                1 => "1 record larger than --pcap-buffer".to_owned(),
+                n => format!("{} records larger than --pcap-buffer", n),
+            });
+        }
+        if report.truncated {
+            places.push("a truncated last record".to_owned());
+        }
+        eprintln!(
+            "{}: Skipped {} bytes of the input, in {}.",
+            style::style("WARNING").with(Color::Yellow).bold(),
+            report.skipped_bytes,
+            places.join(" and ")
+        );
+    }
+    // dropping write_tx closes the channel, which ends the write thread
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a file in the temporary directory, unique to the test run
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}_{}", std::process::id(), name))
+    }
+
+    // a pcapng block in little endian, the body padded to 32 bits
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        body.resize(body.len().div_ceil(4) * 4, 0);
+        let len = (body.len() + 12) as u32;
+        let mut block = [block_type.to_le_bytes(), len.to_le_bytes()].concat();
+        block.extend(body);
+        block.extend(len.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn test_plausible_record() {
+        let header = [
+            0xA1B2C3D4u32.to_le_bytes().as_slice(),
+            &[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &65535u32.to_le_bytes(),
+            &1u32.to_le_bytes(),
+        ]
+        .concat();
+        let (_, header) = parse_pcap_header(&header).unwrap();
+        let record = |ts_s: u32, ts_us: u32, caplen: u32| {
+            let mut record = [ts_s, ts_us, caplen, caplen].map(u32::to_le_bytes).concat();
+            record.resize(PCAP_RECORD_HEADER_LEN + caplen as usize, 0);
+            record
+        };
+        assert!(plausible_record(
+            &header,
+            &record(1_700_000_000, 5, 4),
+            None
+        ));
+        assert!(plausible_record(&header, &[0xFF; 15], None));
+        assert!(!plausible_record(
+            &header,
+            &record(1_700_000_000, 1_000_000, 4),
+            None
+        ));
+        assert!(!plausible_record(
+            &header,
+            &record(1_700_000_000, 5, 300_000),
+            None
+        ));
+        // when resyncing, near the last record and followed by one
+        let resync = Resync {
+            start: Some(24),
+            last_ts: Some(1_700_000_000),
+        };
+        let mut data = record(1_700_000_010, 5, 4);
+        data.extend(record(1_700_000_011, 5, 4));
+        assert!(plausible_record(&header, &data, Some(&resync)));
+        assert!(!plausible_record(
+            &header,
+            &record(1_600_000_000, 5, 4),
+            Some(&resync)
+        ));
+        data.truncate(PCAP_RECORD_HEADER_LEN + 4);
+        data.extend([0xFF; 16]);
+        assert!(!plausible_record(&header, &data, Some(&resync)));
+    }
+
+    #[test]
+    fn test_read_large_records() {
+        // a capture of the loopback interface with a snaplen of 262144
+        let mut capture = [0xA1B2C3D4u32.to_le_bytes().as_slice(), &[2, 0, 4, 0]].concat();
+        capture.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+        capture.extend(262_144u32.to_le_bytes());
+        capture.extend(101u32.to_le_bytes());
+        for len in [100_000u32, 20] {
+            capture.extend([1_700_000_000, 0, len, len].map(u32::to_le_bytes).concat());
+            capture.extend(vec![0xAB; len as usize]);
+        }
+        let path = temp_path("mview_test_read_large_records.pcap");
+        std::fs::write(&path, capture).unwrap();
+        let path = path.to_str().unwrap();
+        let args = Args::parse_from(["mview", "-f", "A:u8", "--pcap", "-i", path]);
+        let (write_tx, write_rx) = crossbeam::channel::unbounded();
+        let report = read_loop(&args, write_tx).unwrap();
+        assert!(!report.is_damaged());
+        let lens: Vec<usize> = write_rx.iter().map(|m| m.payload.len()).collect();
+        assert_eq!(lens, [100_000, 20]);
+        // a smaller buffer can't hold the large record, it is skipped
+        let args = Args::parse_from([
+            "mview",
+            "-f",
+            "A:u8",
+            "--pcap",
+            "-i",
+            path,
+            "--pcap-buffer",
+            "50000",
+        ]);
+        let (write_tx, write_rx) = crossbeam::channel::unbounded();
+        let report = read_loop(&args, write_tx).unwrap();
+        assert_eq!((report.corrupt, report.oversized), (0, 1));
+        assert_eq!(report.skipped_bytes, 100_016);
+        assert_eq!(
+            write_rx.iter().map(|m| m.payload.len()).collect::<Vec<_>>(),
+            [20]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_stream() {
+        // records of 7 bytes, a read of the file doesn't end at one
+        let data: Vec<u8> = (0..40_000).map(|i| (i % 7) as u8).collect();
+        let path = temp_path("mview_test_read_stream.bin");
+        std::fs::write(&path, data).unwrap();
+        let path = path.to_str().unwrap();
+        let args = Args::parse_from(["mview", "-f", "A:u8", "-s", "7", "--stream", "-i", path]);
+        let (write_tx, write_rx) = crossbeam::channel::unbounded();
+        read_loop(&args, write_tx).unwrap();
+        let records: Vec<Vec<u8>> = write_rx.iter().map(|m| m.payload).collect();
+        assert_eq!(records.len(), 5715);
+        assert!(records[..5714].iter().all(|r| *r == [0, 1, 2, 3, 4, 5, 6]));
+        assert_eq!(records[5714], [0, 1]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_follow() {
+        let path = temp_path("mview_test_follow.bin");
+        std::fs::write(&path, b"abc").unwrap();
+        let path = path.to_str().unwrap();
+        let mut follow = Follow::open(path).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(follow.read(&mut buf).unwrap(), 3);
+        // data that comes later is waited for
+        let writer = thread::spawn({
+            let path = path.to_owned();
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+                std::io::Write::write_all(&mut file, b"de").unwrap();
+            }
+        });
+        assert_eq!(follow.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"de");
+        writer.join().unwrap();
+        // a truncated file ends the input
+        std::fs::write(path, b"x").unwrap();
+        assert_eq!(follow.read(&mut buf).unwrap(), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_udp() {
+        // a free port on localhost
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = format!("127.0.0.1:{}", port);
+        let filter = format!("udp and dst port {}", port);
+        let args = Args::parse_from([
+            "mview", "-f", "A:u8", "--udp", &address, "--filter", &filter,
+        ]);
+        let (write_tx, write_rx) = crossbeam::channel::unbounded();
+        let reader = thread::spawn(move || read_loop(&args, write_tx));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // the reader may not listen yet, so send until a datagram arrives
+        let message = (0..100)
+            .find_map(|_| {
+                socket.send_to(b"abc", &address).unwrap();
+                write_rx
+                    .recv_timeout(Duration::from_millis(50))
+                    .ok()
+                    .filter(|message| !message.payload.is_empty())
+            })
+            .expect("no datagram arrived");
+        assert_eq!(message.payload, b"abc");
+        assert!(message.timestamp.is_some());
+        let packet = message.packet.unwrap();
+        assert_eq!(packet.src, socket.local_addr().unwrap());
+        assert_eq!(packet.dst.to_string(), address);
+        // the reader ends without another datagram once the write thread is gone
+        drop(write_rx);
+        assert_eq!(reader.join().unwrap().unwrap(), ReadReport::default());
+    }
+
+    #[test]
+    fn test_ng_block() {
+        let mut interfaces = Vec::new();
+        // an interface with timestamps in ns (if_tsresol 9)
+        let idb = block(
+            1,
+            &[
+                1, 0, 0, 0, 0xff, 0xff, 0, 0, 9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        );
+        let (_, idb) = parse_block_le(&idb).unwrap();
+        assert!(ng_block(&idb, &mut interfaces).is_none());
+        let ts: u64 = 1_700_000_000_123_456_789;
+        let mut body = 0u32.to_le_bytes().to_vec();
+        body.extend(((ts >> 32) as u32).to_le_bytes());
+        body.extend((ts as u32).to_le_bytes());
+        body.extend(3u32.to_le_bytes());
+        body.extend(5u32.to_le_bytes());
+        body.extend([0xAA, 0xBB, 0xCC]);
+        let epb = block(6, &body);
+        let (_, epb) = parse_block_le(&epb).unwrap();
+        let message = ng_block(&epb, &mut interfaces).unwrap();
+        assert_eq!(message.linktype, Some(Linktype::ETHERNET));
+        assert_eq!(
+            message.timestamp.unwrap().to_string(),
+            "2023-11-14 22:13:20.123456789 UTC"
+        );
+        assert_eq!((message.caplen, message.origlen), (3, 5));
+        assert_eq!(message.payload, [0xAA, 0xBB, 0xCC]);
+    }
 }
@@ -0,0 +1,558 @@
+use crate::{
+    args::Args,
+    cheader,
+    mask::{parse_mask, Directive, MaskItem},
+    parse_byte_order, parse_config_line, parse_enum_value, parse_field_attrs, read_config, Format,
+    BYTE_TO_BIT,
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs};
+
+// The structured config formats. They describe the same mask as the config
+// lines, but with room for descriptions, units and enums, and nesting
+// instead of { and } lines. A structured config is turned into config lines
+// when it is read, so the rest of mview only knows config lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Syntax {
+    // the syntax is told by the file extension, anything else is a line config
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_lowercase().as_str() {
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaskDef {
+    #[serde(default)]
+    pub fields: Vec<FieldDef>,
+}
+
+// One entry of a structured mask. Which keys are set decides what it is:
+// a field (name and type), a computed field (name and expr), a directive
+// (at_byte, at_bit, align or repr) or a block (struct, overlay or repeat).
+// A repeat with count is repeated count times, a struct with if is only
+// decoded if the expression is true.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldDef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub val_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_order: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(rename = "enum", skip_serializing_if = "BTreeMap::is_empty")]
+    pub names: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at_byte: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at_bit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub align: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repr: Option<String>,
+    #[serde(rename = "struct", skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<FieldDef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overlay: Option<Vec<FieldDef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<Vec<FieldDef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<String>,
+    #[serde(rename = "if", skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cases: Option<Vec<CaseDef>>,
+}
+
+// the sub-mask of a tlv field for one type
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaseDef {
+    #[serde(rename = "type")]
+    pub tlv_type: usize,
+    #[serde(default)]
+    pub fields: Vec<FieldDef>,
+}
+
+pub fn parse_structured(text: &str, syntax: Syntax) -> Result<MaskDef> {
+    Ok(match syntax {
+        Syntax::Toml => toml::from_str(text)?,
+        Syntax::Yaml => serde_yaml::from_str(text)?,
+        Syntax::Json => serde_json::from_str(text)?,
+    })
+}
+
+pub fn to_structured(mask: &MaskDef, syntax: Syntax) -> Result<String> {
+    Ok(match syntax {
+        Syntax::Toml => toml::to_string(mask)?,
+        Syntax::Yaml => serde_yaml::to_string(mask)?,
+        Syntax::Json => serde_json::to_string_pretty(mask)? + "\n",
+    })
+}
+
+pub fn read_structured(config_path: &str, syntax: Syntax) -> Result<Vec<String>> {
+    let text = fs::read_to_string(config_path)
+        .with_context(|| format!("Could not read config {}", config_path))?;
+    let mask = parse_structured(&text, syntax)
+        .with_context(|| format!("Syntax error in config {}", config_path))?;
+    mask_to_lines(&mask)
+}
+
+// the pieces of a config line can not contain the characters that separate them
+fn check_token(token: &str, what: &str, forbidden: &[char]) -> Result<()> {
+    if token.is_empty() || token.contains(char::is_whitespace) || token.contains(forbidden) {
+        bail!(
+            "Syntax error in config, {} \"{}\" can not be empty or contain whitespace or any of {:?}.",
+            what,
+            token,
+            forbidden
+        );
+    }
+    Ok(())
+}
+
+fn with_description(line: String, description: &Option<String>) -> String {
+    match description {
+        Some(description) => format!("{} # {}", line, description.replace('\n', " ")),
+        None => line,
+    }
+}
+
+fn block_to_lines(
+    head: &str,
+    tail: &str,
+    fields: &[FieldDef],
+    lines: &mut Vec<String>,
+) -> Result<()> {
+    lines.push(format!("{} {{", head));
+    for field in fields {
+        field_to_lines(field, lines)?;
+    }
+    lines.push(format!("}}{}", tail));
+    Ok(())
+}
+
+fn field_to_lines(field: &FieldDef, lines: &mut Vec<String>) -> Result<()> {
+    if let Some(byte) = field.at_byte {
+        lines.push(format!("@byte {}", byte));
+    }
+    if let Some(bit) = field.at_bit {
+        lines.push(format!("@bit {}", bit));
+    }
+    if let Some(align) = field.align {
+        lines.push(format!("align {}", align));
+    }
+    if let Some(repr) = &field.repr {
+        lines.push(format!("repr {}", repr));
+    }
+    match (&field.members, &field.condition) {
+        (Some(members), Some(condition)) => {
+            block_to_lines(&format!("if {}", condition), "", members, lines)?
+        }
+        (Some(members), None) => block_to_lines("struct", "", members, lines)?,
+        (None, Some(_)) => bail!("Syntax error in config, if needs a struct."),
+        (None, None) => {}
+    }
+    if let Some(members) = &field.overlay {
+        block_to_lines("overlay", "", members, lines)?;
+    }
+    if let Some(members) = &field.repeat {
+        let tail = match &field.count {
+            Some(count) => format!(" times {}", count),
+            None => " until end".to_owned(),
+        };
+        block_to_lines("repeat", &tail, members, lines)?;
+    }
+    let name = match &field.name {
+        Some(name) => name,
+        None => {
+            if field.val_type.is_some() || field.expr.is_some() {
+                bail!("Syntax error in config, a field has no name.");
+            }
+            return Ok(());
+        }
+    };
+    check_token(name, "field name", &[':', '#', '=', '{', '}'])?;
+    let mut line = name.clone();
+    if let Some(expr) = &field.expr {
+        if let Some(format) = &field.format {
+            line = format!("{}:{}", line, format);
+        }
+        lines.push(with_description(
+            format!("{} = {}", line, expr),
+            &field.description,
+        ));
+        return Ok(());
+    }
+    let val_type = field
+        .val_type
+        .as_ref()
+        .with_context(|| format!("Syntax error in config, field {} has no type.", name))?;
+    check_token(val_type, "type", &[':', '#'])?;
+    line = format!("{}:{}", line, val_type);
+    if let Some(length) = field.length {
+        line = format!("{}:{}", line, length);
+    }
+    if let Some(format) = &field.format {
+        check_token(format, "format", &[':', '#'])?;
+        line = format!("{}:{}", line, format);
+    }
+    if let Some(byte_order) = &field.byte_order {
+        check_token(byte_order, "byte order", &[':', '#'])?;
+        line = format!("{}:{}", line, byte_order);
+    }
+    if let Some(scale) = field.scale {
+        line = format!("{}:scale={}", line, scale);
+    }
+    if let Some(offset) = field.offset {
+        line = format!("{}:offset={}", line, offset);
+    }
+    if let Some(unit) = &field.unit {
+        check_token(unit, "unit", &[':', '#'])?;
+        line = format!("{}:unit={}", line, unit);
+    }
+    if !field.names.is_empty() {
+        let mut names = Vec::new();
+        for (value, name) in field.names.iter() {
+            let value = parse_enum_value(value).with_context(|| {
+                format!(
+                    "Syntax error in config, enum value {} is not a number.",
+                    value
+                )
+            })?;
+            check_token(name, "enum name", &[':', '#', ',', '=', '(', ')'])?;
+            names.push((value, name));
+        }
+        names.sort();
+        let names: Vec<String> = names
+            .iter()
+            .map(|(value, name)| format!("{}={}", value, name))
+            .collect();
+        line = format!("{}:enum({})", line, names.join(","));
+    }
+    match &field.cases {
+        Some(cases) => {
+            // the description would end up behind the {
+            lines.push(format!("{} {{", line));
+            for case in cases {
+                block_to_lines(&format!("case {}", case.tlv_type), "", &case.fields, lines)?;
+            }
+            lines.push("}".to_owned());
+        }
+        None => lines.push(with_description(line, &field.description)),
+    }
+    Ok(())
+}
+
+// turns a structured mask into config lines
+pub fn mask_to_lines(mask: &MaskDef) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for field in mask.fields.iter() {
+        field_to_lines(field, &mut lines)?;
+    }
+    Ok(lines)
+}
+
+fn format_name(form: Format) -> Option<String> {
+    match form {
+        Format::Norm => None,
+        Format::Hex => Some("hex".to_owned()),
+        Format::Bin => Some("bin".to_owned()),
+    }
+}
+
+fn field_def(conf_line: &str) -> Result<FieldDef> {
+    let (name, val_type, form, len) = parse_config_line(conf_line)?;
+    let attrs = parse_field_attrs(conf_line)?;
+    Ok(FieldDef {
+        name: Some(name.to_owned()),
+        val_type: Some(val_type.to_owned()),
+        length: Some(len).filter(|len| *len > 0),
+        format: format_name(form),
+        byte_order: parse_byte_order(conf_line)?
+            .map(|little_endian| if little_endian { "le" } else { "be" }.to_owned()),
+        scale: attrs.scale,
+        offset: attrs.offset,
+        unit: attrs.unit,
+        names: attrs
+            .names
+            .into_iter()
+            .map(|(value, name)| (value.to_string(), name))
+            .collect(),
+        description: conf_line
+            .split_once('#')
+            .map(|(_, comment)| comment.trim().to_owned())
+            .filter(|comment| !comment.is_empty()),
+        ..Default::default()
+    })
+}
+
+fn items_to_defs(items: &[MaskItem]) -> Result<Vec<FieldDef>> {
+    let mut fields = Vec::new();
+    for item in items {
+        fields.push(match item {
+            MaskItem::Field(conf_line) => field_def(conf_line)?,
+            MaskItem::Directive(Directive::Position(bits)) if bits % BYTE_TO_BIT == 0 => FieldDef {
+                at_byte: Some(bits / BYTE_TO_BIT),
+                ..Default::default()
+            },
+            MaskItem::Directive(Directive::Position(bits)) => FieldDef {
+                at_bit: Some(*bits),
+                ..Default::default()
+            },
+            MaskItem::Directive(Directive::Align(bytes)) => FieldDef {
+                align: Some(*bytes),
+                ..Default::default()
+            },
+            MaskItem::Directive(Directive::Repr(repr_c)) => FieldDef {
+                repr: Some(if *repr_c { "C" } else { "packed" }.to_owned()),
+                ..Default::default()
+            },
+            MaskItem::Struct(members) => FieldDef {
+                members: Some(items_to_defs(members)?),
+                ..Default::default()
+            },
+            MaskItem::Overlay(members) => FieldDef {
+                overlay: Some(items_to_defs(members)?),
+                ..Default::default()
+            },
+            MaskItem::Repeat(count, members) => FieldDef {
+                repeat: Some(items_to_defs(members)?),
+                count: count.as_ref().map(|count| count.to_string()),
+                ..Default::default()
+            },
+            MaskItem::If(condition, members) => FieldDef {
+                members: Some(items_to_defs(members)?),
+                condition: Some(condition.to_string()),
+                ..Default::default()
+            },
+            MaskItem::Tlv(conf_line, cases) => {
+                let mut cases_def = Vec::new();
+                for (tlv_type, members) in cases {
+                    cases_def.push(CaseDef {
+                        tlv_type: *tlv_type,
+                        fields: items_to_defs(members)?,
+                    });
+                }
+                FieldDef {
+                    cases: Some(cases_def).filter(|cases| !cases.is_empty()),
+                    ..field_def(conf_line)?
+                }
+            }
+            MaskItem::Computed(name, expr, form) => FieldDef {
+                name: Some(name.clone()),
+                expr: Some(expr.to_string()),
+                format: format_name(*form),
+                ..Default::default()
+            },
+        });
+    }
+    Ok(fields)
+}
+
+// turns config lines into a structured mask, comments behind fields become descriptions
+pub fn lines_to_mask(config_lines: &[String]) -> Result<MaskDef> {
+    Ok(MaskDef {
+        fields: items_to_defs(&parse_mask(config_lines)?)?,
+    })
+}
+
+// convert a config from one format into another, the formats are told by the
+// file extensions. Without outfile the config lines are printed.
+pub fn convert(infile: &str, outfile: &str, args: &Args) -> Result<()> {
+    let config_lines = read_config(infile, &[], args)
+        .with_context(|| format!("Could not read config {}", infile))?;
+    write_config(&config_lines, outfile)
+}
+
+// the config of a struct of a C header, whatever the name of the header ends in
+pub fn import_c(header: &str, outfile: &str, args: &Args) -> Result<()> {
+    let text =
+        fs::read_to_string(header).with_context(|| format!("Could not read header {}", header))?;
+    let config_lines = cheader::import_c(&text, &args.select, args.little_endian)
+        .with_context(|| format!("Could not import header {}", header))?;
+    write_config(&config_lines, outfile)
+}
+
+// prints the config lines or writes them in the syntax of the outfile
+fn write_config(config_lines: &[String], outfile: &str) -> Result<()> {
+    if outfile.is_empty() {
+        parse_mask(config_lines)?;
+        for line in config_lines.iter() {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    let text = match Syntax::from_path(outfile) {
+        Some(syntax) => to_structured(&lines_to_mask(config_lines)?, syntax)?,
+        None => {
+            // check the config before writing it
+            parse_mask(config_lines)?;
+            config_lines.join("\n") + "\n"
+        }
+    };
+    fs::write(outfile, text).with_context(|| format!("Could not write to {}", outfile))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(config: &str) -> Vec<String> {
+        config.lines().map(|s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_toml_to_lines() {
+        let toml = r#"
+[[fields]]
+name = "Speed"
+type = "u16"
+unit = "km/h"
+description = "speed over ground"
+
+[[fields]]
+name = "State"
+type = "u8"
+format = "hex"
+enum = { 0 = "Off", 1 = "On", 0x10 = "Error" }
+
+[[fields]]
+at_byte = 4
+
+[[fields]]
+overlay = [
+    { name = "Raw", type = "u32" },
+    { name = "Name", type = "string", length = 4 },
+]
+
+[[fields]]
+name = "Doubled"
+expr = "Speed * 2"
+"#;
+        let mask = parse_structured(toml, Syntax::Toml).unwrap();
+        assert_eq!(
+            mask_to_lines(&mask).unwrap(),
+            lines(
+                "Speed:u16:unit=km/h # speed over ground
+State:u8:hex:enum(0=Off,1=On,16=Error)
+@byte 4
+overlay {
+Raw:u32
+Name:string:4
+}
+Doubled = Speed * 2"
+            )
+        );
+    }
+    #[test]
+    fn test_yaml_json_to_lines() {
+        let yaml = "
+fields:
+  - name: Items
+    type: tlv(type=u8,len=u8)
+    cases:
+      - type: 1
+        fields:
+          - { name: Temp, type: i16, unit: C }
+  - repeat:
+      - { name: Flag, type: bool1, enum: { 0: no, 1: yes } }
+";
+        let json = r#"{ "fields": [
+            { "name": "Items", "type": "tlv(type=u8,len=u8)",
+              "cases": [ { "type": 1, "fields": [ { "name": "Temp", "type": "i16", "unit": "C" } ] } ] },
+            { "repeat": [ { "name": "Flag", "type": "bool1", "enum": { "0": "no", "1": "yes" } } ] }
+        ] }"#;
+        let expected = lines(
+            "Items:tlv(type=u8,len=u8) {
+case 1 {
+Temp:i16:unit=C
+}
+}
+repeat {
+Flag:bool1:enum(0=no,1=yes)
+} until end",
+        );
+        let yaml = parse_structured(yaml, Syntax::Yaml).unwrap();
+        let json = parse_structured(json, Syntax::Json).unwrap();
+        assert_eq!(yaml, json);
+        assert_eq!(mask_to_lines(&yaml).unwrap(), expected);
+    }
+    #[test]
+    fn test_structured_errors() {
+        let no_type = "[[fields]]\nname = \"A\"";
+        let mask = parse_structured(no_type, Syntax::Toml).unwrap();
+        assert!(mask_to_lines(&mask).is_err());
+        let space = "[[fields]]\nname = \"A B\"\ntype = \"u8\"";
+        let mask = parse_structured(space, Syntax::Toml).unwrap();
+        assert!(mask_to_lines(&mask).is_err());
+        let unknown_key = "[[fields]]\nname = \"A\"\ntype = \"u8\"\nsize = 3";
+        assert!(parse_structured(unknown_key, Syntax::Toml).is_err());
+    }
+    #[test]
+    fn test_round_trip() {
+        let config = lines(
+            "Id:u16:hex # the id
+Bits:uarb:5:le
+repr C
+Volt:f32:unit=V
+Temp:iarb:8:be:scale=0.5:offset=-40
+Mode:u8:enum(-1=Invalid,1=Fast)
+struct {
+Name:string:8
+}
+@bit 3
+Rest:uarb:5
+Sum = (Id + 1) * 2
+Items:tlv(type=u8,len=u8) {
+case 7 {
+Code:u8
+}
+}
+if Mode == 1 && Id > 2 {
+Extra:u8
+}
+repeat {
+C:u8
+} times Mode + 1
+repeat {
+B:u8
+} until end",
+        );
+        for syntax in [Syntax::Toml, Syntax::Yaml, Syntax::Json] {
+            let text = to_structured(&lines_to_mask(&config).unwrap(), syntax).unwrap();
+            let back = mask_to_lines(&parse_structured(&text, syntax).unwrap()).unwrap();
+            assert_eq!(parse_mask(&back).unwrap(), parse_mask(&config).unwrap());
+            assert_eq!(back[0], "Id:u16:hex # the id");
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use crate::{
+    chunksize_by_config,
+    export::{c_ident, check_unique, is_bit_field, place, storage_bits, Node, Placed},
+    mask::parse_mask,
+    Format, BYTE_TO_BIT,
+};
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+
+// Export of a mask as a Wireshark dissector in Lua. Every field becomes a
+// ProtoField. Bit fields read with le or be are shown with a bit mask,
+// Wireshark then decodes them itself. Fields that Wireshark can not decode
+// like mview (other bit orders, scaled values) are decoded in Lua.
+
+// a bit field like mview reads it, bitpos is counted from the most
+// significant bit of the message
+const MVIEW_BITS: &str = "
+local function mview_bits(buffer, bitpos, len, order, signed)
+    local value = 0
+    for i = 0, len - 1 do
+        local index
+        if order == \"le\" then
+            index = bitpos + i - (bitpos + i) % 8 + 7 - (bitpos + i) % 8
+        elseif order == \"be\" then
+            index = bitpos + len - 1 - i
+        else
+            index = bitpos + i
+        end
+        local byte = buffer(math.floor(index / 8), 1):uint()
+        if math.floor(byte / 2 ^ (7 - index % 8)) % 2 == 1 then
+            value = value + 2 ^ i
+        end
+    end
+    if signed and value >= 2 ^ (len - 1) then
+        value = value - 2 ^ len
+    end
+    return value
+end
+";
+
+// Lua numbers hold integers up to 53 bits exactly
+const MAX_LUA_BITS: usize = 53;
+
+// filter names of Wireshark can only contain letters, digits, -, _ and .
+fn filter_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// Rust escapes strings like Lua does for the characters of names and comments
+fn lua_string(s: &str) -> String {
+    format!("{:?}", s)
+}
+
+struct LuaWriter {
+    proto: String,
+    little_endian: bool,
+    names: HashSet<String>,
+    fields: Vec<String>, // the ProtoField definitions
+    vars: Vec<String>,
+    adds: Vec<String>, // the lines that add the fields to the tree
+    uses_bits: bool,
+}
+
+impl LuaWriter {
+    fn bits_call(&mut self, field: &Placed, order: Option<bool>) -> Result<String> {
+        if field.size > MAX_LUA_BITS {
+            bail!(
+                "Field {} is longer than {} bits, Lua can not decode it.",
+                field.name,
+                MAX_LUA_BITS
+            );
+        }
+        self.uses_bits = true;
+        let order = match order {
+            Some(true) => "\"le\"",
+            Some(false) => "\"be\"",
+            None => "nil",
+        };
+        Ok(format!(
+            "mview_bits(buffer, offset * 8 + {}, {}, {}, {})",
+            field.bitpos,
+            field.size,
+            order,
+            field.val_type == "iarb"
+        ))
+    }
+
+    // the raw value of a scaled field, scale and offset are applied in Lua
+    fn raw_value(&mut self, field: &Placed, range: &str) -> Result<String> {
+        if is_bit_field(&field.val_type) {
+            return self.bits_call(field, field.byte_order);
+        }
+        let little_endian = match field.val_type.as_str() {
+            "f32" | "f64" => field.byte_order == Some(true),
+            _ => field.byte_order.unwrap_or(self.little_endian),
+        };
+        let prefix = if little_endian { "le_" } else { "" };
+        Ok(match field.val_type.as_str() {
+            "f32" | "f64" => format!("{}:{}float()", range, prefix),
+            "u64" => format!("{}:{}uint64():tonumber()", range, prefix),
+            "i64" => format!("{}:{}int64():tonumber()", range, prefix),
+            val_type if val_type.starts_with('i') && field.size <= 32 => {
+                format!("{}:{}int()", range, prefix)
+            }
+            val_type if field.size <= 32 && val_type != "string" => {
+                format!("{}:{}uint()", range, prefix)
+            }
+            _ => bail!(
+                "Field {} of type {} can not be scaled in Lua.",
+                field.name,
+                field.val_type
+            ),
+        })
+    }
+
+    fn field(&mut self, field: &Placed) -> Result<()> {
+        if matches!(field.val_type.as_str(), "bytegap" | "bitgap") {
+            return Ok(());
+        }
+        let var = format!("f_{}", c_ident(&field.name).to_lowercase());
+        check_unique(&mut self.names, &var, &field.name)?;
+        let abbr = lua_string(&format!("{}.{}", self.proto, filter_name(&field.name)));
+        let name = lua_string(&field.name);
+        let base = match field.format {
+            Format::Hex => "base.HEX",
+            _ => "base.DEC",
+        };
+        let names = if field.attrs.names.is_empty() {
+            "nil".to_owned()
+        } else {
+            let names: Vec<String> = field
+                .attrs
+                .names
+                .iter()
+                .map(|(value, name)| format!("[{}] = {}", value, lua_string(name)))
+                .collect();
+            format!("{{ {} }}", names.join(", "))
+        };
+        let description = if field.notes.is_empty() {
+            "nil".to_owned()
+        } else {
+            lua_string(&field.notes.join(", "))
+        };
+        let offset = field.bitpos % BYTE_TO_BIT;
+        let n_bytes = (offset + field.size).div_ceil(BYTE_TO_BIT);
+        let range = format!(
+            "buffer(offset + {}, {})",
+            field.bitpos / BYTE_TO_BIT,
+            n_bytes
+        );
+        if !is_bit_field(&field.val_type) && offset != 0 {
+            bail!(
+                "Field {} does not start at a byte, in the dissector only bit fields can.",
+                field.name
+            );
+        }
+        let little_endian = match field.val_type.as_str() {
+            "f32" | "f64" => field.byte_order == Some(true),
+            _ => field.byte_order.unwrap_or(self.little_endian),
+        };
+        let add = if little_endian { "add_le" } else { "add" };
+        // a single bit is read the same way without a byte order and with be
+        let bit_order = match field.byte_order {
+            None if field.size == 1 => Some(false),
+            byte_order => byte_order,
+        };
+        let (definition, add_line) = if field.attrs.is_scaled() {
+            let raw = self.raw_value(field, &range)?;
+            let mut value = raw;
+            if let Some(scale) = field.attrs.scale {
+                value = format!("{} * {:?}", value, scale);
+            }
+            match field.attrs.offset {
+                Some(offset) if offset < 0.0 => value = format!("{} - {:?}", value, -offset),
+                Some(offset) => value = format!("{} + {:?}", value, offset),
+                None => {}
+            }
+            (
+                format!(
+                    "ProtoField.double({}, {}, nil, {})",
+                    abbr, name, description
+                ),
+                format!("subtree:add({}, {}, {})", var, range, value),
+            )
+        } else {
+            match field.val_type.as_str() {
+                "bool1" | "uarb" | "iarb" if bit_order.is_some() && n_bytes <= 4 => {
+                    // Wireshark reads the bytes as an integer and masks the field out
+                    let width = match n_bytes {
+                        1 => 8,
+                        2 => 16,
+                        3 => 24,
+                        _ => 32,
+                    };
+                    let shift = if bit_order == Some(true) {
+                        offset
+                    } else {
+                        n_bytes * BYTE_TO_BIT - offset - field.size
+                    };
+                    let mask = format!("0x{:X}", ((1u64 << field.size) - 1) << shift);
+                    let definition = match field.val_type.as_str() {
+                        "bool1" => format!(
+                            "ProtoField.bool({}, {}, {}, nil, {}, {})",
+                            abbr, name, width, mask, description
+                        ),
+                        val_type => format!(
+                            "ProtoField.{}int{}({}, {}, {}, {}, {}, {})",
+                            if val_type == "iarb" { "" } else { "u" },
+                            width,
+                            abbr,
+                            name,
+                            base,
+                            names,
+                            mask,
+                            description
+                        ),
+                    };
+                    let add = if bit_order == Some(true) {
+                        "add_le"
+                    } else {
+                        "add"
+                    };
+                    (definition, format!("subtree:{}({}, {})", add, var, range))
+                }
+                "bool1" | "uarb" | "iarb" => {
+                    let value = self.bits_call(field, field.byte_order)?;
+                    let definition = match field.val_type.as_str() {
+                        "bool1" => format!(
+                            "ProtoField.bool({}, {}, base.NONE, nil, nil, {})",
+                            abbr, name, description
+                        ),
+                        val_type => format!(
+                            "ProtoField.{}int{}({}, {}, {}, {}, nil, {})",
+                            if val_type == "iarb" { "" } else { "u" },
+                            storage_bits(field.size)?,
+                            abbr,
+                            name,
+                            base,
+                            names,
+                            description
+                        ),
+                    };
+                    let value = if field.val_type == "bool1" {
+                        format!("{} == 1", value)
+                    } else {
+                        value
+                    };
+                    (
+                        definition,
+                        format!("subtree:add({}, {}, {})", var, range, value),
+                    )
+                }
+                "bool8" => (
+                    format!(
+                        "ProtoField.bool({}, {}, 8, nil, 0xFF, {})",
+                        abbr, name, description
+                    ),
+                    format!("subtree:add({}, {})", var, range),
+                ),
+                "string" => (
+                    format!(
+                        "ProtoField.string({}, {}, base.ASCII, {})",
+                        abbr, name, description
+                    ),
+                    format!("subtree:add({}, {})", var, range),
+                ),
+                "u128" | "i128" => (
+                    format!(
+                        "ProtoField.bytes({}, {}, base.NONE, {})",
+                        abbr, name, description
+                    ),
+                    format!("subtree:add({}, {})", var, range),
+                ),
+                "f32" | "f64" => (
+                    format!(
+                        "ProtoField.{}({}, {}, nil, {})",
+                        if field.val_type == "f32" {
+                            "float"
+                        } else {
+                            "double"
+                        },
+                        abbr,
+                        name,
+                        description
+                    ),
+                    format!("subtree:{}({}, {})", add, var, range),
+                ),
+                val_type => (
+                    format!(
+                        "ProtoField.{}int{}({}, {}, {}, {}, nil, {})",
+                        if val_type.starts_with('u') { "u" } else { "" },
+                        field.size,
+                        abbr,
+                        name,
+                        base,
+                        names,
+                        description
+                    ),
+                    format!("subtree:{}({}, {})", add, var, range),
+                ),
+            }
+        };
+        self.fields.push(format!("local {} = {}", var, definition));
+        self.vars.push(var);
+        self.adds.push(format!("        {}", add_line));
+        Ok(())
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) -> Result<()> {
+        for node in nodes.iter() {
+            match node {
+                Node::Field(field) => self.field(field)?,
+                Node::Overlay(_, _, alternatives) => {
+                    for alternative in alternatives.iter() {
+                        self.nodes(alternative)?;
+                    }
+                }
+                Node::Computed(computed) => self
+                    .adds
+                    .push(format!("        -- computed by mview: {}", computed)),
+            }
+        }
+        Ok(())
+    }
+}
+
+// a dissector that decodes every chunk of a message like mview. Without a
+// udp port it can be chosen with Decode As.
+pub fn export_lua(
+    config_lines: &[String],
+    name: &str,
+    little_endian: bool,
+    udp_port: Option<u16>,
+) -> Result<String> {
+    let mut nodes = Vec::new();
+    place(
+        &parse_mask(config_lines)?,
+        &mut 0,
+        &mut false,
+        "",
+        &mut nodes,
+    )?;
+    let size = chunksize_by_config(config_lines)?.div_ceil(BYTE_TO_BIT);
+    if size == 0 {
+        bail!("The config has no fields to export.");
+    }
+    let proto = c_ident(name).to_lowercase();
+    let mut writer = LuaWriter {
+        proto: proto.clone(),
+        little_endian,
+        names: HashSet::new(),
+        fields: Vec::new(),
+        vars: Vec::new(),
+        adds: Vec::new(),
+        uses_bits: false,
+    };
+    writer.nodes(&nodes)?;
+    let mut out = vec![format!(
+        "local mview_proto = Proto({}, {})",
+        lua_string(&proto),
+        lua_string(&format!("{} (mview)", name))
+    )];
+    out.extend(writer.fields);
+    out.push(format!(
+        "mview_proto.fields = {{ {} }}",
+        writer.vars.join(", ")
+    ));
+    if writer.uses_bits {
+        out.push(MVIEW_BITS.to_owned());
+    } else {
+        out.push(String::new());
+    }
+    out.push(format!("local SIZE = {}", size));
+    out.push(String::new());
+    out.push("function mview_proto.dissector(buffer, pinfo, tree)".to_owned());
+    out.push("    pinfo.cols.protocol = mview_proto.name".to_owned());
+    out.push("    -- every chunk of the message is decoded, like in mview".to_owned());
+    out.push("    for chunk = 0, math.floor(buffer:len() / SIZE) - 1 do".to_owned());
+    out.push("        local offset = chunk * SIZE".to_owned());
+    out.push(format!(
+        "        local subtree = tree:add(mview_proto, buffer(offset, SIZE), {} .. chunk)",
+        lua_string(&format!("{}, chunk ", name))
+    ));
+    out.extend(writer.adds);
+    out.push("    end".to_owned());
+    out.push("end".to_owned());
+    out.push(String::new());
+    out.push(match udp_port {
+        Some(port) => format!(
+            "DissectorTable.get(\"udp.port\"):add({}, mview_proto)",
+            port
+        ),
+        None => "DissectorTable.get(\"udp.port\"):add_for_decode_as(mview_proto)".to_owned(),
+    });
+    Ok(out.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(config: &str) -> Vec<String> {
+        config.lines().map(|s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_export_lua() {
+        let config = lines(
+            "Id:u16:hex # the id
+Flags:uarb:3:le:enum(1=Ready)
+Sign:iarb:5:be
+Odd:uarb:8
+Temp:u8:scale=0.5:offset=-40:unit=degC
+Gap:bytegap:1
+Volt:f32:le",
+        );
+        let lua = export_lua(&config, "status", true, Some(17224)).unwrap();
+        let lua: Vec<&str> = lua.lines().collect();
+        assert_eq!(
+            lua[..8],
+            [
+                "local mview_proto = Proto(\"status\", \"status (mview)\")",
+                "local f_id = ProtoField.uint16(\"status.Id\", \"Id\", base.HEX, nil, nil, \"the id\")",
+                "local f_flags = ProtoField.uint8(\"status.Flags\", \"Flags\", base.DEC, { [1] = \"Ready\" }, 0x7, \"1=Ready\")",
+                "local f_sign = ProtoField.int8(\"status.Sign\", \"Sign\", base.DEC, nil, 0x1F, nil)",
+                "local f_odd = ProtoField.uint8(\"status.Odd\", \"Odd\", base.DEC, nil, nil, nil)",
+                "local f_temp = ProtoField.double(\"status.Temp\", \"Temp\", nil, \"unit degC, scale 0.5, offset -40\")",
+                "local f_volt = ProtoField.float(\"status.Volt\", \"Volt\", nil, nil)",
+                "mview_proto.fields = { f_id, f_flags, f_sign, f_odd, f_temp, f_volt }",
+            ]
+        );
+        assert!(lua.contains(&"local SIZE = 10"));
+        assert!(lua.contains(&"        subtree:add_le(f_id, buffer(offset + 0, 2))"));
+        assert!(lua.contains(&"        subtree:add_le(f_flags, buffer(offset + 2, 1))"));
+        assert!(lua.contains(&"        subtree:add(f_sign, buffer(offset + 2, 1))"));
+        assert!(lua.contains(
+            &"        subtree:add(f_odd, buffer(offset + 3, 1), mview_bits(buffer, offset * 8 + 24, 8, nil, false))"
+        ));
+        assert!(lua.contains(
+            &"        subtree:add(f_temp, buffer(offset + 4, 1), buffer(offset + 4, 1):le_uint() * 0.5 - 40.0)"
+        ));
+        assert!(lua.contains(&"        subtree:add_le(f_volt, buffer(offset + 6, 4))"));
+        assert_eq!(
+            lua.last(),
+            Some(&"DissectorTable.get(\"udp.port\"):add(17224, mview_proto)")
+        );
+    }
+}
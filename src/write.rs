@@ -1,9 +1,16 @@
 use crate::{
-    args::Args, chunksize_by_config, count_lines, format_number, parse_config_line,
-    print_additional, print_bitpos, read_config, size_in_bits, Format, PcapMsgHeader, PcapTs,
-    BIN_LINE_SIZE, HEX_LINE_SIZE,
+    args::Args,
+    chunksize_by_config, count_lines,
+    expr::{eval, Expr, Scope, Value},
+    format_number,
+    mask::{
+        align_bitpos, apply_directive, is_open_ended, item_alignment, parse_mask, parse_tlv_type,
+        MaskCursor, MaskItem,
+    },
+    parse_byte_order, parse_config_line, parse_field_attrs, print_additional, print_bitpos,
+    read_config, size_in_bits, Format, Message, BIN_LINE_SIZE, BYTE_TO_BIT, HEX_LINE_SIZE,
 };
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bitvec::{
     macros::internal::funty::{Fundamental, Integral},
     prelude::*,
@@ -12,15 +19,12 @@ use core::time;
 use crossbeam::channel::Receiver;
 use crossterm::style::{self, Color, Stylize};
 use crossterm::{cursor, execute, terminal};
-use std::sync::{Arc, Mutex};
 use std::{
     fs::File,
     io::{self, BufWriter, Write},
     thread,
 };
 
-const PCAP_BLOCK_HEADER_LEN: u8 = 16;
-
 #[derive(Default)]
 pub struct Stats {
     pub message_count: u32,
@@ -29,12 +33,10 @@ pub struct Stats {
     pub chunk_start: u32,
     pub hex_lines: usize,
     pub bin_lines: usize,
+    pub truncated: bool, // the current message, caplen < origlen in pcap input
+    pub truncated_count: u32, // truncated messages so far
 }
-pub fn write_loop(
-    args: &Args,
-    write_rx: Receiver<Vec<u8>>,
-    pcap_ts: Arc<Mutex<PcapTs>>,
-) -> Result<()> {
+pub fn write_loop(args: &Args, write_rx: Receiver<Message>) -> Result<()> {
     // break what is read into chunks and apply config lines as masked to it
     let is_stdout = args.outfile.is_empty();
     let mut writer: Box<dyn Write> = if !is_stdout {
@@ -44,48 +46,57 @@ pub fn write_loop(
     };
     let mut first_run = true;
     let mut stats: Stats = Default::default();
-    let config_lines = read_config(&args.config)?;
+    let config_lines = read_config(&args.config, &args.mask_lines, args)?;
     let chunksize_from_config = chunksize_by_config(&config_lines)?; // bits!
+    let mask = parse_mask(&config_lines)?;
+    // with repeat or tlv in the mask and no chunksize given, a chunk is the whole message
+    let open_ended = args.chunksize < 1 && is_open_ended(&mask);
+    // number of lines printed for the last chunk, to know how far to jump back
+    let mut n_field_lines = 0;
     let mut chunksize = args.chunksize;
     if chunksize < 1 {
         // bytes!
         // if the chunksize from arguments is invalid, get the config chunks
         chunksize = chunksize_from_config / 8;
     }
-    if chunksize_from_config % size_in_bits::<u8>() > 0 {
+    if chunksize_from_config % size_in_bits::<u8>() > 0 && !open_ended {
         eprintln!("{}: Size of config is {} bytes and {} bits. The chunksize is {} bytes.
 this means that some fields in the config will not be considered in the output because chunksize does not match sum of the fields sizes in config.", style::style("WARNING").with(Color::Yellow).bold(), chunksize_from_config / 8, chunksize_from_config % 8, chunksize)
     }
-    loop {
-        let mut buffer = write_rx
-            .recv()
-            .context("Error recieving data from read thread.")?;
+    // the read thread is done when the channel is closed
+    while let Ok(message) = write_rx.recv() {
+        let buffer = &message.payload;
         if buffer.is_empty() {
-            break;
+            // nothing to show, like a stripped TCP packet without payload
+            continue;
         }
-        let pcapmsgheader = PcapMsgHeader::new(&mut buffer);
-        if args.pcap {
-            for _i in 0..PCAP_BLOCK_HEADER_LEN {
-                // TODO maybe use VecDeque here instead, popping from front
-                // and shifting is bad performance
-                buffer.remove(0);
-            }
+        if open_ended {
+            chunksize = buffer.len().max(1);
         }
         // get some stats
         stats.message_count += 1;
         stats.chunk_count = 0;
         stats.message_len = buffer.len().as_u32();
+        stats.truncated = message.is_truncated();
+        if stats.truncated {
+            stats.truncated_count += 1;
+        }
         let chunkiter = buffer
             .chunks(chunksize)
             .take(1)
-            .last()
+            .next_back()
             .context("Could not get size of chunk.")?;
         stats.hex_lines = chunkiter.chunks(HEX_LINE_SIZE).count();
         stats.bin_lines = chunkiter.chunks(BIN_LINE_SIZE).count();
 
         // when we read from stdin, return is pressed by the user after typing in a message.
         // In this case we need to get rid of the extra line
-        if is_stdout && !first_run && args.cursor_jump && args.infile.is_empty() {
+        if is_stdout
+            && !first_run
+            && args.cursor_jump
+            && args.infile.is_empty()
+            && args.udp.is_none()
+        {
             execute!(io::stdout(), cursor::MoveUp(1))?;
         }
 
@@ -97,7 +108,7 @@ this means that some fields in the config will not be considered in the output b
             // in case we write to stdout, move the cursor back to the start
             if is_stdout {
                 if !first_run && args.cursor_jump && !args.clear {
-                    move_cursor(args, config_lines.len(), &stats)?;
+                    move_cursor(args, n_field_lines, &stats)?;
                 }
                 if args.clear {
                     execute!(
@@ -108,42 +119,280 @@ this means that some fields in the config will not be considered in the output b
                 }
             }
 
-            print_additional(
-                args,
-                &pcapmsgheader,
-                &pcap_ts,
-                &stats,
-                &mut writer,
-                chunk,
-                chunksize,
-            )?;
+            print_additional(args, &message, &stats, &mut writer, chunk, chunksize)?;
+
+            let mut cursor = MaskCursor::new(args.bitoffset + args.offset * size_in_bits::<u8>());
+            let mut scope = Scope::new();
+            n_field_lines =
+                write_items(args, &mask, chunk, &mut cursor, "", &mut scope, &mut writer)?;
+            // print an empty line at the end of every chunk
+            writer
+                .write_all(b"\n")
+                .context("Could now write to writer")?;
+            thread::sleep(time::Duration::from_millis(args.pause));
+            first_run = false;
+        }
+    }
+    Ok(())
+}
 
-            let mut bitpos_in_chunk = args.bitoffset + args.offset * size_in_bits::<u8>();
-            // strategy: for every config line we call write_line().
-            // write_line() will parse the config line, then get the size of the data type it
-            // found it that line from the chunk, print it out and advance bitpos_in_chunk accordingly
-            for conf_line in config_lines.iter() {
+// strategy: for every field in the mask we call write_line().
+// write_line() will parse the config line, then get the size of the data type it
+// found it that line from the chunk, print it out and advance the cursor accordingly
+// Directives (@pos, align n, repr(C)) are applied to the cursor directly,
+// blocks are walked recursively.
+// prefix is printed in front of every field, it holds the index of repeated elements.
+// The values of the fields are collected in scope, computed fields are evaluated over it.
+// Returns the number of field lines that were printed.
+pub fn write_items(
+    args: &Args,
+    items: &[MaskItem],
+    chunk: &[u8],
+    cursor: &mut MaskCursor,
+    prefix: &str,
+    scope: &mut Scope,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let mut n_lines = 0;
+    for item in items.iter() {
+        if cursor.repr_c {
+            cursor.bitpos = align_bitpos(cursor.bitpos, cursor.mask_start, item_alignment(item)?);
+        }
+        match item {
+            MaskItem::Field(conf_line) => {
                 if args.print_bitpos {
-                    print_bitpos(&mut writer, bitpos_in_chunk)?;
+                    print_bitpos(writer, cursor.bitpos)?;
                 }
-                write_line(
+                writer
+                    .write_all(prefix.as_bytes())
+                    .context("Could now write to writer")?;
+                let value = write_line(
                     args,
                     conf_line,
                     chunk,
-                    &mut bitpos_in_chunk,
-                    &mut writer,
+                    &mut cursor.bitpos,
+                    writer,
                     args.little_endian,
                 )?;
+                if let Some(value) = value {
+                    scope.insert(parse_config_line(conf_line)?.0.to_owned(), value);
+                }
+                n_lines += 1;
             }
-            // print an empty line at the end of every chunk
+            MaskItem::Directive(directive) => apply_directive(
+                directive,
+                &mut cursor.bitpos,
+                cursor.mask_start,
+                &mut cursor.repr_c,
+            ),
+            MaskItem::Overlay(members) => {
+                // decode every member from the same position, then continue
+                // behind the largest one
+                let start = cursor.bitpos;
+                let mut overlay_end = start;
+                for member in members.iter() {
+                    cursor.bitpos = start;
+                    n_lines += write_items(
+                        args,
+                        std::slice::from_ref(member),
+                        chunk,
+                        cursor,
+                        prefix,
+                        scope,
+                        writer,
+                    )?;
+                    overlay_end = overlay_end.max(cursor.bitpos);
+                }
+                cursor.bitpos = overlay_end;
+            }
+            MaskItem::Struct(members) => {
+                n_lines += write_items(args, members, chunk, cursor, prefix, scope, writer)?;
+            }
+            MaskItem::Repeat(count, body) => {
+                // without a count the body is repeated until the end of the chunk
+                let count = match count {
+                    Some(expr) => match eval(expr, scope) {
+                        Ok(Value::Int(n)) => Some(n),
+                        result => {
+                            write_failed_expr(writer, prefix, "repeat times", expr, result)?;
+                            n_lines += 1;
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                let mut index = 0;
+                while cursor.bitpos < chunk.len() * size_in_bits::<u8>()
+                    && count.is_none_or(|n| index < n)
+                {
+                    let start = cursor.bitpos;
+                    let element_prefix = format!("{}[{}] ", prefix, index);
+                    n_lines +=
+                        write_items(args, body, chunk, cursor, &element_prefix, scope, writer)?;
+                    if cursor.bitpos <= start {
+                        // nothing of the chunk was consumed, we would loop forever
+                        break;
+                    }
+                    index += 1;
+                }
+            }
+            MaskItem::If(condition, body) => match eval(condition, scope) {
+                Ok(value) if value.is_true() => {
+                    n_lines += write_items(args, body, chunk, cursor, prefix, scope, writer)?;
+                }
+                Ok(_) => {}
+                result => {
+                    write_failed_expr(writer, prefix, "if", condition, result)?;
+                    n_lines += 1;
+                }
+            },
+            MaskItem::Tlv(..) => {
+                n_lines += write_tlv(args, item, chunk, cursor, prefix, scope, writer)?;
+            }
+            MaskItem::Computed(name, expr, form) => {
+                if args.print_bitpos {
+                    print_bitpos(writer, cursor.bitpos)?;
+                }
+                let result = eval(expr, scope);
+                let text = match result {
+                    Ok(Value::Int(i)) => format_number(i, *form),
+                    Ok(Value::Float(f)) => f.to_string(),
+                    Err(ref e) => format!("could not be computed: {}", e),
+                };
+                writer
+                    .write_fmt(format_args!("{}{}: {}\n", prefix, name, text))
+                    .context("Could now write to writer")?;
+                if let Ok(value) = result {
+                    scope.insert(name.to_owned(), value);
+                }
+                n_lines += 1;
+            }
+        }
+    }
+    Ok(n_lines)
+}
+
+// a line for a condition or count that could not be used
+fn write_failed_expr(
+    writer: &mut dyn Write,
+    prefix: &str,
+    what: &str,
+    expr: &Expr,
+    result: Result<Value>,
+) -> Result<()> {
+    let reason = match result {
+        Ok(value) => format!("{} is not an integer", value),
+        Err(e) => format!("could not be computed: {}", e),
+    };
+    writer
+        .write_fmt(format_args!("{}{} {}: {}\n", prefix, what, expr, reason))
+        .context("Could now write to writer")
+}
+
+fn load_unsigned(
+    c_bits: &BitSlice<u8, Msb0>,
+    bitpos: usize,
+    bits: usize,
+    little_endian: bool,
+) -> u64 {
+    let mut myslice = bitvec![u8, Msb0; 0; bits];
+    myslice.copy_from_bitslice(&c_bits[bitpos..bitpos + bits]);
+    if little_endian {
+        myslice.load_le::<u64>()
+    } else {
+        myslice.load_be::<u64>()
+    }
+}
+
+// print the elements of a tlv field until the chunk is exhausted. If there is a
+// case block for the type of an element, the value is decoded with that sub-mask,
+// otherwise it is printed as hex.
+fn write_tlv(
+    args: &Args,
+    tlv: &MaskItem,
+    chunk: &[u8],
+    cursor: &mut MaskCursor,
+    prefix: &str,
+    scope: &mut Scope,
+    writer: &mut dyn Write,
+) -> Result<usize> {
+    let MaskItem::Tlv(conf_line, cases) = tlv else {
+        bail!("Not a tlv field.");
+    };
+    let c_bits = chunk.view_bits::<Msb0>();
+    let (fieldname, val_type, _, _) = parse_config_line(conf_line)?;
+    let (type_bits, len_bits) = parse_tlv_type(val_type)?;
+    let mut n_lines = 0;
+    let mut index = 0;
+    while cursor.bitpos < c_bits.len() {
+        if args.print_bitpos {
+            print_bitpos(writer, cursor.bitpos)?;
+        }
+        writer
+            .write_fmt(format_args!("{}[{}] {}: ", prefix, index, fieldname))
+            .context("Could now write to writer")?;
+        n_lines += 1;
+        let value_start = cursor.bitpos + type_bits + len_bits;
+        if value_start > c_bits.len() {
             writer
-                .write_all(b"\n")
+                .write_all(b"values size is bigger than what is left of that data chunk\n")
                 .context("Could now write to writer")?;
-            thread::sleep(time::Duration::from_millis(args.pause));
-            first_run = false;
+            break;
+        }
+        let tlv_type = load_unsigned(c_bits, cursor.bitpos, type_bits, args.little_endian);
+        let tlv_len = load_unsigned(
+            c_bits,
+            cursor.bitpos + type_bits,
+            len_bits,
+            args.little_endian,
+        ) as usize;
+        // a length read from the data can be anything
+        let value_end = tlv_len
+            .checked_mul(size_in_bits::<u8>())
+            .and_then(|bits| bits.checked_add(value_start))
+            .filter(|&end| end <= c_bits.len());
+        let Some(value_end) = value_end else {
+            writer
+                .write_fmt(format_args!(
+                    "type {}, length {}, values size is bigger than what is left of that data chunk\n",
+                    tlv_type, tlv_len
+                ))
+                .context("Could now write to writer")?;
+            break;
+        };
+        // copy the value over, this way the sub-mask can not read past it
+        let value = c_bits[value_start..value_end].to_bitvec().into_vec();
+        match cases.iter().find(|(case, _)| *case as u64 == tlv_type) {
+            Some((_, sub_mask)) => {
+                writer
+                    .write_fmt(format_args!("type {}, length {}\n", tlv_type, tlv_len))
+                    .context("Could now write to writer")?;
+                let mut sub_cursor = MaskCursor::new(0);
+                sub_cursor.repr_c = cursor.repr_c;
+                let element_prefix = format!("{}[{}] ", prefix, index);
+                n_lines += write_items(
+                    args,
+                    sub_mask,
+                    &value,
+                    &mut sub_cursor,
+                    &element_prefix,
+                    scope,
+                    writer,
+                )?;
+            }
+            None => {
+                writer
+                    .write_fmt(format_args!(
+                        "type {}, length {}, value {:02X?}\n",
+                        tlv_type, tlv_len, value
+                    ))
+                    .context("Could now write to writer")?;
+            }
         }
+        cursor.bitpos = value_end;
+        index += 1;
     }
-    Ok(())
+    Ok(n_lines)
 }
 
 pub fn move_cursor(args: &Args, n_conf_lines: usize, stats: &Stats) -> Result<()> {
@@ -166,36 +415,31 @@ pub fn write_integer_data<T>(
     writer: &mut dyn Write,
     format: Format,
     little_endian: bool,
-) -> Result<usize>
+) -> Result<(usize, Option<Value>)>
 where
     T: Integral,
 {
-    // returns the size of the written type in bits
+    // returns the size of the written type in bits and its value
+    let mut value = None;
     if *bitpos_in_chunk + size_in_bits::<T>() <= c_bits.len() {
         let mut myslice = bitvec![u8, Msb0; 0; size_in_bits::<T>()];
         myslice
             .copy_from_bitslice(&c_bits[*bitpos_in_chunk..*bitpos_in_chunk + size_in_bits::<T>()]);
-        if little_endian {
-            writer
-                .write_fmt(format_args!(
-                    "{}\n",
-                    format_number(myslice[0..size_in_bits::<T>()].load_le::<T>(), format)
-                ))
-                .context("Could now write to writer")?;
+        let num = if little_endian {
+            myslice[0..size_in_bits::<T>()].load_le::<T>()
         } else {
-            writer
-                .write_fmt(format_args!(
-                    "{}\n",
-                    format_number(myslice[0..size_in_bits::<T>()].load_be::<T>(), format)
-                ))
-                .context("Could now write to writer")?;
-        }
+            myslice[0..size_in_bits::<T>()].load_be::<T>()
+        };
+        writer
+            .write_fmt(format_args!("{}\n", format_number(num, format)))
+            .context("Could now write to writer")?;
+        value = Some(Value::Int(num.as_i128()));
     } else {
         writer
             .write_all(b"values size is bigger than what is left of that data chunk\n")
             .context("Could now write to writer")?;
     }
-    Ok(size_in_bits::<T>())
+    Ok((size_in_bits::<T>(), value))
 }
 
 fn write_gap(
@@ -225,9 +469,84 @@ pub fn write_line(
     bitpos_in_chunk: &mut usize,
     writer: &mut dyn Write,
     little_endian: bool,
-) -> Result<()> {
+) -> Result<Option<Value>> {
+    // returns the value of the field if it is a number, for computed fields
+    let attrs = parse_field_attrs(conf_line)?;
+    if attrs.is_empty() {
+        return write_value(
+            args,
+            conf_line,
+            chunk,
+            bitpos_in_chunk,
+            writer,
+            little_endian,
+        );
+    }
+    // unit and enum name go behind the value, so the line is assembled first
+    let mut line = Vec::new();
+    let mut value = write_value(
+        args,
+        conf_line,
+        chunk,
+        bitpos_in_chunk,
+        &mut line,
+        little_endian,
+    )?;
+    if let Some(raw) = value {
+        // enum names are for the raw value
+        let name = match raw {
+            Value::Int(i) => attrs.name_of(i),
+            Value::Float(_) => None,
+        };
+        if attrs.is_scaled() {
+            let scaled = Value::Float(attrs.scaled(raw.as_f64()));
+            let (fieldname, _, _, _) = parse_config_line(conf_line)?;
+            line = format!("{}: {}", fieldname, scaled).into_bytes();
+            value = Some(scaled);
+        } else if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        if let Some(unit) = &attrs.unit {
+            line.extend_from_slice(format!(" {}", unit).as_bytes());
+        }
+        if let Some(name) = name {
+            line.extend_from_slice(format!(" ({})", name).as_bytes());
+        }
+        line.push(b'\n');
+    }
+    writer
+        .write_all(&line)
+        .context("Could now write to writer")?;
+    writer.flush().context("Could now write to writer")?;
+    Ok(value)
+}
+
+// index in the chunk of bit i (counted from the least significant bit of the
+// value) of an iarb / uarb field that starts at bitpos, see parse_byte_order()
+fn arb_bit_index(bitpos: usize, i: usize, len: usize, byte_order: Option<bool>) -> usize {
+    match byte_order {
+        None => bitpos + i,
+        Some(true) => {
+            let pos = bitpos + i;
+            pos - pos % BYTE_TO_BIT + (BYTE_TO_BIT - 1 - pos % BYTE_TO_BIT)
+        }
+        Some(false) => bitpos + len - 1 - i,
+    }
+}
+
+fn write_value(
+    args: &Args,
+    conf_line: &str,
+    chunk: &[u8],
+    bitpos_in_chunk: &mut usize,
+    writer: &mut dyn Write,
+    little_endian: bool,
+) -> Result<Option<Value>> {
     let c_bits = chunk.view_bits::<Msb0>();
     let (fieldname, val_type, form, len) = parse_config_line(conf_line)?;
+    let byte_order = parse_byte_order(conf_line)?;
+    let little_endian = byte_order.unwrap_or(little_endian);
+    let mut value = None;
     writer
         .write_fmt(format_args!("{}", fieldname))
         .context("Could now write to writer")?;
@@ -237,9 +556,11 @@ pub fn write_line(
     let val_type = val_type.to_lowercase(); // don't care about case fo the letters
     match val_type.as_str() {
         "bool1" => {
+            let bit = c_bits[arb_bit_index(*bitpos_in_chunk, 0, 1, byte_order)];
             writer
-                .write_fmt(format_args!("{}\n", c_bits[*bitpos_in_chunk]))
+                .write_fmt(format_args!("{}\n", bit))
                 .context("Could now write to writer")?;
+            value = Some(Value::Int(bit as i128));
             *bitpos_in_chunk += 1;
         }
         "bool8" => {
@@ -248,9 +569,11 @@ pub fn write_line(
                 myslice.copy_from_bitslice(
                     &c_bits[*bitpos_in_chunk..*bitpos_in_chunk + size_in_bits::<u8>()],
                 );
+                let b = myslice[0..8].load::<u8>() > 0;
                 writer
-                    .write_fmt(format_args!("{}\n", myslice[0..8].load::<u8>() > 0))
+                    .write_fmt(format_args!("{}\n", b))
                     .context("Could now write to writer")?;
+                value = Some(Value::Int(b as i128));
             } else {
                 writer
                     .write_all(b"values size is bigger than what is left of that data chunk\n")
@@ -259,44 +582,64 @@ pub fn write_line(
             *bitpos_in_chunk += size_in_bits::<u8>();
         }
         "u8" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<u8>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "u16" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<u16>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "u32" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<u32>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "u64" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<u64>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "u128" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<u128>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "i8" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<i8>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "i16" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<i16>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "i32" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<i32>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "i64" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<i64>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "i128" => {
-            *bitpos_in_chunk +=
+            let (size, num) =
                 write_integer_data::<i128>(bitpos_in_chunk, c_bits, writer, form, little_endian)?;
+            *bitpos_in_chunk += size;
+            value = num;
         }
         "f32" => {
             if *bitpos_in_chunk + size_in_bits::<f32>() <= c_bits.len() {
@@ -304,12 +647,16 @@ pub fn write_line(
                 myslice.copy_from_bitslice(
                     &c_bits[*bitpos_in_chunk..*bitpos_in_chunk + size_in_bits::<f32>()],
                 );
+                // --le is only for integers, floats follow a le of the field
+                let f = f32::from_bits(if byte_order == Some(true) {
+                    myslice[0..32].load_le::<u32>()
+                } else {
+                    myslice[0..32].load_be::<u32>()
+                });
                 writer
-                    .write_fmt(format_args!(
-                        "{}\n",
-                        f32::from_bits(myslice[0..32].load_be::<u32>())
-                    ))
+                    .write_fmt(format_args!("{}\n", f))
                     .context("Could now write to writer")?;
+                value = Some(Value::Float(f as f64));
             } else {
                 writer
                     .write_all(b"values size is bigger than what is left of that data chunk\n")
@@ -323,12 +670,15 @@ pub fn write_line(
                 myslice.copy_from_bitslice(
                     &c_bits[*bitpos_in_chunk..*bitpos_in_chunk + size_in_bits::<f64>()],
                 );
+                let f = f64::from_bits(if byte_order == Some(true) {
+                    myslice[0..64].load_le::<u64>()
+                } else {
+                    myslice[0..64].load_be::<u64>()
+                });
                 writer
-                    .write_fmt(format_args!(
-                        "{}\n",
-                        f64::from_bits(myslice[0..64].load_be::<u64>())
-                    ))
+                    .write_fmt(format_args!("{}\n", f))
                     .context("Could now write to writer")?;
+                value = Some(Value::Float(f));
             } else {
                 writer
                     .write_all(b"values size is bigger than what is left of that data chunk\n")
@@ -364,11 +714,15 @@ pub fn write_line(
         }
         "iarb" => {
             if *bitpos_in_chunk + len <= c_bits.len() {
-                let negative = c_bits[*bitpos_in_chunk + len - 1];
+                let negative = c_bits[arb_bit_index(*bitpos_in_chunk, len - 1, len, byte_order)];
                 let mut target_slice: [u8; 16] = [0; 16];
                 let int_bits = target_slice.view_bits_mut::<Lsb0>();
                 for i in 0..len {
-                    int_bits.set(i, c_bits[*bitpos_in_chunk + i]); // copy the payload over
+                    // copy the payload over
+                    int_bits.set(
+                        i,
+                        c_bits[arb_bit_index(*bitpos_in_chunk, i, len, byte_order)],
+                    );
                 }
                 if negative {
                     // integer is negative, do the twos complement
@@ -380,6 +734,7 @@ pub fn write_line(
                 writer
                     .write_fmt(format_args!("{}\n", target_int))
                     .context("Could now write to writer")?;
+                value = Some(Value::Int(target_int));
                 *bitpos_in_chunk += len;
             } else {
                 writer
@@ -392,12 +747,17 @@ pub fn write_line(
                 let mut target_slice: [u8; 16] = [0; 16];
                 let int_bits = target_slice.view_bits_mut::<Lsb0>();
                 for i in 0..len {
-                    int_bits.set(i, c_bits[*bitpos_in_chunk + i]); // copy the payload over
+                    // copy the payload over
+                    int_bits.set(
+                        i,
+                        c_bits[arb_bit_index(*bitpos_in_chunk, i, len, byte_order)],
+                    );
                 }
                 let target_int = int_bits.load::<i128>();
                 writer
                     .write_fmt(format_args!("{}\n", target_int))
                     .context("Could now write to writer")?;
+                value = Some(Value::Int(target_int));
 
                 *bitpos_in_chunk += len;
             } else {
@@ -416,7 +776,7 @@ pub fn write_line(
     }
     writer.flush().context("Could now write to writer")?;
 
-    Ok(())
+    Ok(value)
 }
 
 #[cfg(test)]
@@ -432,6 +792,7 @@ mod tests {
             infile: "nil".to_string(),
             outfile: "nil".to_string(),
             config: "nil".to_string(),
+            mask_lines: Vec::new(),
             pcap: false,
             chunksize: 0,
             offset: 0,
@@ -443,11 +804,22 @@ mod tests {
             little_endian: false,
             timestamp: false,
             read_head: 0,
+            stream: false,
+            follow: false,
+            udp: None,
+            join: Vec::new(),
             print_statistics: false,
             print_bitpos: false,
+            layout: false,
+            strip_headers: false,
+            print_addresses: false,
+            filter: None,
+            pcap_buffer: crate::MAX_PCAP_BUFFER,
             cursor_jump: false,
             clear: false,
             filter_newlines: false,
+            select: String::new(),
+            subcommand: None,
         }
     }
 
@@ -485,6 +857,86 @@ mod tests {
         assert_eq!(output, format_write_line_output("true").as_bytes());
     }
     #[test]
+    fn test_write_line_unit_and_enum() {
+        let args = make_dummy_args();
+        let chunk: [u8; 2] = [0x01, 0x07];
+        let mut bitpos_in_chunk = 0;
+        let mut output = Vec::new();
+        for conf_line in ["Test:u8:unit=V:enum(1=On)", "Test:u8:hex:enum(1=On)"] {
+            write_line(
+                &args,
+                conf_line,
+                &chunk,
+                &mut bitpos_in_chunk,
+                &mut output,
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(output, b"Test: 1 V (On)\nTest: 0x07\n");
+    }
+    #[test]
+    fn test_write_line_scale_offset() {
+        let args = make_dummy_args();
+        let chunk: [u8; 2] = [0x14, 0x32];
+        let mut bitpos_in_chunk = 0;
+        let mut output = Vec::new();
+        for conf_line in ["Test:u8:scale=0.5:unit=V", "Test:u8:offset=-40"] {
+            write_line(
+                &args,
+                conf_line,
+                &chunk,
+                &mut bitpos_in_chunk,
+                &mut output,
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(output, b"Test: 10 V\nTest: 10\n");
+    }
+    #[test]
+    fn test_write_line_byte_order() {
+        let args = make_dummy_args();
+        // 3 bit and 9 bit fields, like C bitfields on a little endian machine
+        // and on a big endian machine
+        let chunk: [u8; 4] = [0b1000_1101, 0b0000_0001, 0b1011_0000, 0b0000_0001];
+        let mut bitpos_in_chunk = 0;
+        let mut output = Vec::new();
+        for conf_line in [
+            "Test:uarb:3:le",
+            "Test:uarb:9:le",
+            "Test:uarb:3:be",
+            "Test:uarb:9:be",
+            "Test:bool1:le",
+        ] {
+            write_line(
+                &args,
+                conf_line,
+                &chunk,
+                &mut bitpos_in_chunk,
+                &mut output,
+                false,
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            output,
+            b"Test: 5\nTest: 49\nTest: 0\nTest: 432\nTest: true\n"
+        );
+        let mut bitpos_in_chunk = 0;
+        let mut output = Vec::new();
+        write_line(
+            &args,
+            "Test:u16:le",
+            &chunk,
+            &mut bitpos_in_chunk,
+            &mut output,
+            false,
+        )
+        .unwrap();
+        assert_eq!(output, format_write_line_output(0x018D).as_bytes());
+    }
+    #[test]
     fn test_write_line_bool1_false() {
         let args = make_dummy_args();
         let conf_line = "Test:bool1";
@@ -1352,4 +1804,222 @@ mod tests {
             format_write_line_output("(gap of 1 bit)").as_bytes()
         );
     }
+    #[test]
+    fn test_write_items_overlay() {
+        let args = make_dummy_args();
+        let config = "overlay {
+Raw:u32:hex
+Float:f32
+Text:String:4
+struct {
+High:u16
+Low:u16
+}
+}
+After:u8";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let mask = parse_mask(&config_lines).unwrap();
+        let chunk: [u8; 5] = [0x40, 0x49, 0x0F, 0xDB, b'x'];
+        let mut cursor = MaskCursor::new(0);
+
+        let mut output = Vec::new();
+        assert_eq!(
+            write_items(
+                &args,
+                &mask,
+                &chunk,
+                &mut cursor,
+                "",
+                &mut Scope::new(),
+                &mut output
+            )
+            .unwrap(),
+            6
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Raw: 0x40490FDB\nFloat: 3.1415927\nText: @I\u{f}\u{fffd}\nHigh: 16457\nLow: 4059\nAfter: 120\n"
+        );
+        assert_eq!(cursor.bitpos, 40);
+    }
+    #[test]
+    fn test_write_items_repeat() {
+        let args = make_dummy_args();
+        let config = "Count:u8
+repeat {
+Id:u8
+Value:u16
+} until end";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let mask = parse_mask(&config_lines).unwrap();
+        let chunk: [u8; 7] = [2, 1, 0x01, 0x00, 2, 0x00, 0x10];
+        let mut cursor = MaskCursor::new(0);
+
+        let mut output = Vec::new();
+        assert_eq!(
+            write_items(
+                &args,
+                &mask,
+                &chunk,
+                &mut cursor,
+                "",
+                &mut Scope::new(),
+                &mut output
+            )
+            .unwrap(),
+            5
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Count: 2\n[0] Id: 1\n[0] Value: 256\n[1] Id: 2\n[1] Value: 16\n"
+        );
+    }
+    #[test]
+    fn test_write_items_if_and_times() {
+        let args = make_dummy_args();
+        let config = "Count:u8
+repeat {
+Id:u8
+} times Count
+if Count > 1 {
+Big:u8
+}
+if Count > 5 {
+Huge:u8
+}
+if Unknown {
+}";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let mask = parse_mask(&config_lines).unwrap();
+        let chunk: [u8; 5] = [2, 7, 8, 9, 10];
+        let mut cursor = MaskCursor::new(0);
+
+        let mut output = Vec::new();
+        assert_eq!(
+            write_items(
+                &args,
+                &mask,
+                &chunk,
+                &mut cursor,
+                "",
+                &mut Scope::new(),
+                &mut output
+            )
+            .unwrap(),
+            5
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Count: 2\n[0] Id: 7\n[1] Id: 8\nBig: 9\nif Unknown: could not be computed: No value for field Unknown.\n"
+        );
+    }
+    #[test]
+    fn test_write_items_tlv() {
+        let args = make_dummy_args();
+        let config = "Items:tlv(type=u8,len=u16) {
+case 1 {
+Temperature:i16
+}
+}";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let mask = parse_mask(&config_lines).unwrap();
+        let chunk: [u8; 13] = [
+            1, 0, 2, 0xFF, 0xFE, // known type, decoded with the sub-mask
+            7, 0, 3, 0xAA, 0xBB, 0xCC, // unknown type, printed as hex
+            9, 0, // cut off
+        ];
+        let mut cursor = MaskCursor::new(0);
+
+        let mut output = Vec::new();
+        assert_eq!(
+            write_items(
+                &args,
+                &mask,
+                &chunk,
+                &mut cursor,
+                "",
+                &mut Scope::new(),
+                &mut output
+            )
+            .unwrap(),
+            4
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "[0] Items: type 1, length 2
+[0] Temperature: -2
+[1] Items: type 7, length 3, value [AA, BB, CC]
+[2] Items: values size is bigger than what is left of that data chunk
+"
+        );
+    }
+    #[test]
+    fn test_write_items_tlv_huge_length() {
+        let args = make_dummy_args();
+        let mask = parse_mask(&["Items:tlv(type=u8,len=u64)".to_owned()]).unwrap();
+        let mut chunk = vec![1];
+        chunk.extend([0xFF; 8]);
+        chunk.extend([0, 0]);
+        let mut output = Vec::new();
+        write_items(
+            &args,
+            &mask,
+            &chunk,
+            &mut MaskCursor::new(0),
+            "",
+            &mut Scope::new(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            format!(
+                "[0] Items: type 1, length {}, values size is bigger than what is left of that data chunk\n",
+                u64::MAX
+            )
+        );
+    }
+    #[test]
+    fn test_write_items_computed() {
+        let args = make_dummy_args();
+        let config = "Hi:u8
+Lo:u8
+Voltage:f32
+Id:hex = (Hi << 8) | Lo
+Power = Voltage * Lo
+Broken = Missing + 1";
+        let config_lines: Vec<String> = config.lines().map(|s| s.to_owned()).collect();
+        let mask = parse_mask(&config_lines).unwrap();
+        let chunk: [u8; 6] = [0x12, 0x02, 0x41, 0x48, 0x00, 0x00];
+        let mut cursor = MaskCursor::new(0);
+        let mut scope = Scope::new();
+
+        let mut output = Vec::new();
+        assert_eq!(
+            write_items(
+                &args,
+                &mask,
+                &chunk,
+                &mut cursor,
+                "",
+                &mut scope,
+                &mut output
+            )
+            .unwrap(),
+            6
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Hi: 18
+Lo: 2
+Voltage: 12.5
+Id: 0x1202
+Power: 25
+Broken: could not be computed: No value for field Missing.
+"
+        );
+        // computed fields take no space in the chunk
+        assert_eq!(cursor.bitpos, 48);
+        assert_eq!(scope.get("Power"), Some(&Value::Float(25.0)));
+    }
 }